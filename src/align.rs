@@ -0,0 +1,225 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use image::DynamicImage;
+
+/// Pages with more inserted plus removed rows than this are compared position by position,
+/// since aligning them would take too long to be worthwhile.
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+/// How a row of the diff image maps to rows of the trusted and testing screenshots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowPair {
+    /// Row `.0` of the trusted screenshot is compared against row `.1` of the testing screenshot.
+    Both(u32, u32),
+    /// Row only exists in the trusted screenshot, i.e. it was removed.
+    TrustedOnly(u32),
+    /// Row only exists in the testing screenshot, i.e. it was inserted.
+    TestingOnly(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Hashes every row of pixels in `img`, so rows can be compared like lines of text.
+pub fn hash_rows(img: &DynamicImage) -> Vec<u64> {
+    let rgba = img.to_rgba();
+    let row_len = rgba.width() as usize * 4;
+    if row_len == 0 {
+        return vec![0; rgba.height() as usize];
+    }
+    rgba.into_raw()
+        .chunks(row_len)
+        .map(|row| {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Pairs rows by position: row `n` of one screenshot against row `n` of the other.
+/// Rows past the end of the shorter screenshot only exist in the taller one.
+pub fn positional_rows(trusted_height: u32, testing_height: u32) -> Vec<RowPair> {
+    (0..trusted_height.max(testing_height))
+        .map(|y| {
+            if y < trusted_height && y < testing_height {
+                RowPair::Both(y, y)
+            } else if y < trusted_height {
+                RowPair::TrustedOnly(y)
+            } else {
+                RowPair::TestingOnly(y)
+            }
+        })
+        .collect()
+}
+
+/// Aligns rows of two screenshots the way a text diff aligns lines, so content that only moved
+/// up or down is paired with itself.
+///
+/// Runs of removed rows directly followed by inserted rows are paired up as changed rows, and
+/// only what is left over is reported as inserted or removed.
+pub fn align_rows(trusted: &[u64], testing: &[u64]) -> Vec<RowPair> {
+    let prefix = trusted.iter().zip(testing).take_while(|(a, b)| a == b).count();
+    let suffix = trusted[prefix..].iter().rev()
+        .zip(testing[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let trusted_middle = &trusted[prefix..trusted.len() - suffix];
+    let testing_middle = &testing[prefix..testing.len() - suffix];
+    let middle = match myers(trusted_middle, testing_middle, MAX_EDIT_DISTANCE) {
+        Some(edits) => edits,
+        None => return positional_rows(trusted.len() as u32, testing.len() as u32),
+    };
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Equal(i, i)).collect();
+    edits.extend(middle.into_iter().map(|edit| match edit {
+        Edit::Equal(a, b) => Edit::Equal(a + prefix, b + prefix),
+        Edit::Delete(a) => Edit::Delete(a + prefix),
+        Edit::Insert(b) => Edit::Insert(b + prefix),
+    }));
+    edits.extend((0..suffix).map(|i| {
+        Edit::Equal(trusted.len() - suffix + i, testing.len() - suffix + i)
+    }));
+
+    edits_to_pairs(&edits)
+}
+
+fn edits_to_pairs(edits: &[Edit]) -> Vec<RowPair> {
+    let mut pairs = vec![];
+    let mut removed: Vec<u32> = vec![];
+    let mut inserted: Vec<u32> = vec![];
+
+    let flush = |pairs: &mut Vec<RowPair>, removed: &mut Vec<u32>, inserted: &mut Vec<u32>| {
+        let changed = removed.len().min(inserted.len());
+        pairs.extend(removed.iter().zip(inserted.iter()).map(|(a, b)| RowPair::Both(*a, *b)));
+        pairs.extend(removed[changed..].iter().map(|a| RowPair::TrustedOnly(*a)));
+        pairs.extend(inserted[changed..].iter().map(|b| RowPair::TestingOnly(*b)));
+        removed.clear();
+        inserted.clear();
+    };
+
+    for edit in edits {
+        match *edit {
+            Edit::Equal(a, b) => {
+                flush(&mut pairs, &mut removed, &mut inserted);
+                pairs.push(RowPair::Both(a as u32, b as u32));
+            }
+            Edit::Delete(a) => removed.push(a as u32),
+            Edit::Insert(b) => inserted.push(b as u32),
+        }
+    }
+    flush(&mut pairs, &mut removed, &mut inserted);
+    pairs
+}
+
+/// Myers' O(ND) shortest edit script between `a` and `b`.
+/// Returns `None` when more than `max_d` insertions and deletions would be needed.
+fn myers(a: &[u64], b: &[u64], max_d: usize) -> Option<Vec<Edit>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // trace[d] holds v for k in -(d + 1)..=(d + 1) as it was before step d.
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    for d in 0..=(max.min(max_d) as isize) {
+        let lo = (offset - d - 1) as usize;
+        let hi = (offset + d + 1) as usize;
+        trace.push(v[lo..=hi].to_vec());
+
+        let mut k = -d;
+        while k <= d {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+            k += 2;
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Edit> {
+    let mut edits = vec![];
+    let mut x = n;
+    let mut y = m;
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_rows_shift_the_rest_of_the_page() {
+        let pairs = align_rows(&[1, 2, 3, 4], &[1, 8, 9, 2, 3, 4]);
+        assert_eq!(pairs, vec![
+            RowPair::Both(0, 0),
+            RowPair::TestingOnly(1),
+            RowPair::TestingOnly(2),
+            RowPair::Both(1, 3),
+            RowPair::Both(2, 4),
+            RowPair::Both(3, 5),
+        ]);
+    }
+
+    #[test]
+    fn changed_rows_are_paired_and_removed_rows_reported() {
+        let pairs = align_rows(&[1, 2, 3, 4, 5], &[1, 7, 4]);
+        assert_eq!(pairs, vec![
+            RowPair::Both(0, 0),
+            RowPair::Both(1, 1),
+            RowPair::TrustedOnly(2),
+            RowPair::Both(3, 2),
+            RowPair::TrustedOnly(4),
+        ]);
+    }
+}
@@ -0,0 +1,113 @@
+//! Approving testing screenshots as the baselines later runs are compared against.
+
+use std::error::Error;
+use std::fs;
+
+use serde_json;
+
+use crate::config::Config;
+use crate::report::{RouteReport, Status, Summary};
+use crate::url_utils;
+
+/// Where the summary of the last run is kept, for `approve` to find its screenshots.
+pub fn last_run_path(config: &Config) -> String {
+    format!("{}/last_run.json", config.screenshots)
+}
+
+/// Base name of the screenshot files of `report`.
+fn pic_name(report: &RouteReport) -> String {
+    match &report.selector {
+        Some(selector) => url_utils::get_element_pic_name(&report.route, &report.viewport, selector, report.index),
+        None => url_utils::get_pic_name(&report.route, &report.viewport),
+    }
+}
+
+/// Base names of the screenshot files behind `report`. A route whose capture failed as a whole
+/// has a single report, but may still have screenshots for each of its `capture_selectors`, and
+/// for each element when a selector matched several.
+fn pic_names(config: &Config, report: &RouteReport) -> Vec<String> {
+    let selectors = config.capture_selectors_for(&report.route);
+    if report.selector.is_some() || selectors.is_empty() {
+        return vec![pic_name(report)];
+    }
+    let mut pic_names = vec![];
+    for selector in &selectors {
+        let pic_name = url_utils::get_element_pic_name(&report.route, &report.viewport, selector, None);
+        pic_names.extend(url_utils::find_indexed_pic_names(&config.screenshots, &pic_name, "_testing.png"));
+        pic_names.push(pic_name);
+    }
+    pic_names
+}
+
+/// Reports of the last run to approve: every capture of `routes`, or without any, every capture
+/// that failed or errored. Skipped routes weren't captured, so they are never approved.
+fn to_approve<'a>(summary: &'a Summary, routes: &[String]) -> Vec<&'a RouteReport> {
+    summary.routes.iter()
+        .filter(|report| if routes.is_empty() {
+            report.status == Status::Failed || report.status == Status::Errored
+        } else {
+            routes.contains(&report.route) && report.status != Status::Skipped
+        })
+        .collect()
+}
+
+/// Copies testing screenshots of the last run into `baselines`, and returns the baseline paths
+/// written.
+///
+/// Captures that never got as far as a testing screenshot are left out. Each capture removes the
+/// route's screenshots from earlier runs first, so any testing screenshot found is from the last
+/// run.
+pub fn approve(config: &Config, routes: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let baselines = match &config.baselines {
+        Some(baselines) => baselines,
+        None => return Err("No baselines folder to approve screenshots into. Set one with --baselines".into()),
+    };
+    let last_run = last_run_path(config);
+    let summary: Summary = serde_json::from_str(&fs::read_to_string(&last_run).map_err(|e| {
+        format!("Unable to read the last run's summary \"{}\": {}", last_run, e)
+    })?)?;
+
+    fs::create_dir_all(baselines)?;
+    let mut approved = vec![];
+    for report in to_approve(&summary, routes) {
+        for pic_name in pic_names(config, report) {
+            let testing_path = format!("{}/{}_testing.png", config.screenshots, pic_name);
+            if fs::metadata(&testing_path).is_err() {
+                continue;
+            }
+            let baseline_path = url_utils::get_baseline_path(baselines, &pic_name);
+            fs::copy(&testing_path, &baseline_path)?;
+            approved.push(baseline_path);
+        }
+    }
+    Ok(approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(route: &str, viewport: &str, status: Status) -> RouteReport {
+        RouteReport::new(route, None, viewport, "/shots", route, status, 0.0)
+    }
+
+    #[test]
+    fn approves_failures_unless_routes_are_given() {
+        let summary = Summary::new(vec![
+            report("about", "desktop", Status::Passed),
+            report("blog", "desktop", Status::Failed),
+            report("blog", "mobile", Status::Passed),
+            report("shop", "desktop", Status::Errored),
+            report("huge", "desktop", Status::Skipped),
+        ]);
+        let names = |reports: Vec<&RouteReport>| {
+            reports.iter().map(|report| pic_name(report)).collect::<Vec<String>>()
+        };
+
+        assert_eq!(names(to_approve(&summary, &[])), vec!["blog_desktop", "shop_desktop"]);
+        assert_eq!(
+            names(to_approve(&summary, &[String::from("blog"), String::from("about"), String::from("huge")])),
+            vec!["about_desktop", "blog_desktop", "blog_mobile"],
+        );
+    }
+}
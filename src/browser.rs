@@ -1,33 +1,301 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::{Write};
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 
 use headless_chrome::{
     protocol::browser::Bounds,
-    protocol::page::ScreenshotFormat,
+    protocol::page::{ScreenshotFormat, Viewport},
     Browser,
+    Element,
     LaunchOptionsBuilder,
+    Tab,
 };
 use md5;
 
+use super::cdp;
+use super::intercept;
+use super::config::{Login, ViewportProfile};
+use super::readiness;
+use super::report;
 use super::url_utils;
+use super::regions::Rect;
+use super::route_log::RouteLog;
+use super::tiles;
 use colored::*;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread::sleep;
 
-fn calculate_render_sleep(px_in_capture: &u32) -> Duration {
+fn calculate_render_sleep(px_in_capture: &u32, base: Duration, per_megapixel: Duration) -> Duration {
     // Very large pages need more time to render.
-    // This seems like a reasonable default scale
-    //
+    // With the default 100 ms per million px,
     // a 2000 px by 20000 px page would be 40 million px,
     // and this function would calculate a delay of 4 seconds.
 
-    // rounding occurs, so don't use from_seconds or too much precision is lost
+    // The default 30 ms base is an arbitrary amount to account for device/network slowness
+    base + per_megapixel * *px_in_capture / 1_000_000
+}
+
+/// Waits until every tab in `tabs` is ready to capture.
+///
+/// Tabs are checked against the configured readiness conditions. Without any, this falls back to
+/// sleeping for a time estimated from `px_in_capture`.
+fn wait_for_render(
+    tabs: &[&Tab],
+    config: &crate::config::Config,
+    px_in_capture: u32,
+    log: &mut RouteLog,
+) -> Result<(), Box<dyn Error>> {
+    let conditions = readiness::conditions(config);
+    if conditions.is_empty() {
+        let estimated_render_time = calculate_render_sleep(
+            &px_in_capture,
+            Duration::from_millis(config.render_wait_base_ms),
+            Duration::from_millis(config.render_wait_per_megapixel_ms),
+        );
+        log.println(format_args!("wait {:?} for render...", estimated_render_time));
+        sleep(estimated_render_time);
+    } else {
+        log.println("waiting for page to be ready...");
+        for tab in tabs {
+            readiness::wait_until_ready(tab, &conditions)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stops CSS animations and transitions, and hides the blinking text caret.
+const DISABLE_ANIMATIONS_CSS: &str = "*, *::before, *::after { \
+    animation: none !important; \
+    transition: none !important; \
+    caret-color: transparent !important; \
+}";
+
+/// Stylesheet injected into both pages before capture, or an empty string if there is nothing
+/// to inject.
+///
+/// Each selector gets its own rule, so one invalid selector doesn't unhide the others.
+fn stabilize_stylesheet(hide_selectors: &[String], disable_animations: bool) -> String {
+    let mut rules: Vec<String> = hide_selectors
+        .iter()
+        .map(|selector| format!("{} {{ visibility: hidden !important; }}", selector))
+        .collect();
+    if disable_animations {
+        rules.push(String::from(DISABLE_ANIMATIONS_CSS));
+    }
+    rules.join("\n")
+}
+
+/// Injects `stylesheet` and runs the user's `script` in the page `body` belongs to.
+///
+/// The script runs as the body of an async function with `this` bound to `body`,
+/// and is awaited before returning.
+fn stabilize(body: &Element, stylesheet: &str, script: &Option<String>) -> Result<(), Box<dyn Error>> {
+    if !stylesheet.is_empty() {
+        body.call_js_fn(&format!(
+            "function() {{
+                const style = this.ownerDocument.createElement(\"style\");
+                style.textContent = {};
+                (this.ownerDocument.head || this).appendChild(style);
+            }}",
+            serde_json::to_string(stylesheet)?,
+        ), false)?;
+    }
+    if let Some(script) = script {
+        body.call_js_fn(&format!("async function() {{\n{}\n}}", script), true)?;
+    }
+    Ok(())
+}
+
+/// Script run at document start that freezes the clock at `freeze_time` (milliseconds since
+/// the Unix epoch) and makes `Math.random` a generator seeded with `random_seed`.
+/// Returns `None` when neither is set.
+fn deterministic_script(freeze_time: Option<i64>, random_seed: Option<u32>) -> Option<String> {
+    if freeze_time.is_none() && random_seed.is_none() {
+        return None;
+    }
+    let mut script = String::from("(function() {\n");
+    if let Some(time) = freeze_time {
+        script.push_str(&format!("
+    var frozenTime = {};
+    var RealDate = Date;
+    function FrozenDate() {{
+        if (!(this instanceof FrozenDate)) {{
+            return new RealDate(frozenTime).toString();
+        }}
+        var args = Array.prototype.slice.call(arguments);
+        return args.length ? new (Function.prototype.bind.apply(RealDate, [null].concat(args)))() : new RealDate(frozenTime);
+    }}
+    FrozenDate.prototype = RealDate.prototype;
+    FrozenDate.now = function() {{ return frozenTime; }};
+    FrozenDate.parse = RealDate.parse;
+    FrozenDate.UTC = RealDate.UTC;
+    window.Date = FrozenDate;
+", time));
+    }
+    if let Some(seed) = random_seed {
+        // mulberry32, a small and fast seedable generator.
+        script.push_str(&format!("
+    var state = {};
+    Math.random = function() {{
+        state = (state + 0x6D2B79F5) >>> 0;
+        var t = state;
+        t = Math.imul(t ^ (t >>> 15), t | 1);
+        t ^= t + Math.imul(t ^ (t >>> 7), t | 61);
+        return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+    }};
+", seed));
+    }
+    script.push_str("})();");
+    Some(script)
+}
+
+/// What `capture_snapshots` learned about a route's trusted and testing pages, or about one
+/// element on them.
+#[derive(Debug)]
+pub struct Capture {
+    /// The `capture_selectors` entry the screenshots are clipped to, or `None` for the whole page.
+    pub selector: Option<String>,
+    /// Which of the elements matching `selector` the screenshots are of, when it matched more
+    /// than one.
+    pub index: Option<usize>,
+    /// Base name of the screenshot files, e.g. `blog_desktop`.
+    pub pic_name: String,
+    /// True when both screenshots are byte for byte identical.
+    pub images_identical: bool,
+    /// Areas of the trusted screenshot covered by elements matching `ignore_selectors`.
+    pub trusted_ignore: Vec<Rect>,
+    /// Areas of the testing screenshot covered by elements matching `ignore_selectors`.
+    pub testing_ignore: Vec<Rect>,
+    /// Requests blocked while the route's pages were captured. Only the route's first capture
+    /// counts them, so they are reported once.
+    pub blocked: usize,
+}
+
+/// Box models of elements matching `selectors`, relative to the captured `viewport`.
+/// `scale` is the number of screenshot pixels per CSS pixel.
+fn find_ignored_rects(tab: &Tab, selectors: &[String], viewport: &Viewport, scale: f64) -> Vec<Rect> {
+    let mut rects = vec![];
+    for selector in selectors {
+        // A selector may only match on one of the pages, so no match is not an error.
+        let elements = tab.find_elements(selector).unwrap_or_default();
+        for element in elements {
+            // Elements that are not rendered have no box model.
+            if let Ok(box_model) = element.get_box_model() {
+                let margin = box_model.margin_viewport();
+                let left = ((margin.x - viewport.x) * scale).floor().max(0.0);
+                let top = ((margin.y - viewport.y) * scale).floor().max(0.0);
+                let right = ((margin.x + margin.width - viewport.x) * scale).ceil().max(left);
+                let bottom = ((margin.y + margin.height - viewport.y) * scale).ceil().max(top);
+                rects.push(Rect {
+                    x: left as u32,
+                    y: top as u32,
+                    width: (right - left) as u32,
+                    height: (bottom - top) as u32,
+                });
+            }
+        }
+    }
+    rects
+}
 
-    // 30 ms delay is an arbitrary amount to account for device/network slowness
-    // TODO: Make these values configurable to users
-    Duration::from_micros((30_000 + px_in_capture / 10).into())
+/// A launched browser and how many routes it has captured.
+struct PooledBrowser {
+    browser: Browser,
+    uses: usize,
+}
+
+/// Long-lived browsers shared by every route in a run, so Chrome is not relaunched per route.
+///
+/// A browser is relaunched once it has captured `max_browser_uses` routes, or when it stops
+/// responding.
+pub struct BrowserPool {
+    config: crate::config::Config,
+    idle: Mutex<Vec<PooledBrowser>>,
+}
+
+impl BrowserPool {
+    pub fn new(config: &crate::config::Config) -> BrowserPool {
+        BrowserPool {
+            config: config.clone(),
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    /// Takes an idle, healthy browser, launching one if there are none.
+    fn checkout(&self) -> Result<PooledBrowser, Box<dyn Error>> {
+        loop {
+            let pooled = self.idle.lock().unwrap().pop();
+            match pooled {
+                // Dropping a browser kills its Chrome process.
+                Some(pooled) if pooled.uses >= self.config.max_browser_uses => drop(pooled),
+                Some(pooled) => {
+                    if pooled.browser.get_version().is_ok() {
+                        return Ok(pooled);
+                    }
+                    println!("{}", "browser stopped responding, relaunching...".yellow());
+                }
+                None => {
+                    return Ok(PooledBrowser {
+                        browser: make_browser(&self.config)?,
+                        uses: 0,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Runs `f` with `count` fresh tabs in one of the pool's browsers, and closes the tabs
+    /// afterwards.
+    fn with_tabs<T>(
+        &self,
+        count: usize,
+        f: impl FnOnce(&[Arc<Tab>]) -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let mut pooled = self.checkout()?;
+        let mut tabs = vec![];
+        for _ in 0..count {
+            tabs.push(pooled.browser.new_tab()?);
+        }
+        let result = f(&tabs);
+        pooled.uses += 1;
+        let mut all_closed = true;
+        for tab in &tabs {
+            all_closed &= close_tab(&pooled.browser, tab).is_ok();
+        }
+        if all_closed {
+            self.idle.lock().unwrap().push(pooled);
+        }
+        result
+    }
+
+    /// Runs `f` with a fresh tab in one of the pool's browsers, and closes the tab afterwards.
+    pub fn with_tab<T>(
+        &self,
+        f: impl FnOnce(&Tab) -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        self.with_tabs(1, |tabs| f(&tabs[0]))
+    }
+
+    /// Like `with_tab`, but with two tabs in the same browser.
+    pub fn with_tab_pair<T>(
+        &self,
+        f: impl FnOnce(&Tab, &Tab) -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        self.with_tabs(2, |tabs| f(&tabs[0], &tabs[1]))
+    }
+}
+
+/// Closes `tab` and forgets it, since `Browser` keeps every tab it has seen otherwise.
+fn close_tab(browser: &Browser, tab: &Tab) -> Result<(), Box<dyn Error>> {
+    let target_id = tab.get_target_id().clone();
+    let closed = tab.call_method(cdp::CloseTarget { target_id: target_id.clone() })?;
+    if !closed.success {
+        return Err(format!("Unable to close tab {}", target_id).into());
+    }
+    browser.get_tabs().lock().unwrap().retain(|open| open.get_target_id() != &target_id);
+    Ok(())
 }
 
 pub fn make_browser(config: &crate::config::Config) -> Result<Browser, Box<dyn Error>> {
@@ -37,31 +305,542 @@ pub fn make_browser(config: &crate::config::Config) -> Result<Browser, Box<dyn E
         .idle_browser_timeout(Duration::new(40, 0))
         .build()?;
     let browser = Browser::new(browser_options)?;
-    let _tab = browser.wait_for_initial_tab()?;
+    let tab = browser.wait_for_initial_tab()?;
+    start_session(&tab, config)?;
     Ok(browser)
 }
 
+/// Sets the configured cookies and logs in to both domains in a newly launched browser.
+/// Cookies are shared by every tab, so this only has to happen once per browser.
+fn start_session(tab: &Tab, config: &crate::config::Config) -> Result<(), Box<dyn Error>> {
+    // The login form needs the configured headers too. Nothing is captured in this tab, so its
+    // blocked requests aren't counted.
+    intercept::intercept_requests(tab, config)?;
+    for (domain, cookies) in &[(&config.trusted, &config.trusted_cookies), (&config.testing, &config.testing_cookies)] {
+        if cookies.is_empty() {
+            continue;
+        }
+        let cookies = cookies.iter()
+            .map(|cookie| cdp::CookieParam {
+                name: cookie.name.clone(),
+                value: cookie.value.clone(),
+                url: domain.to_string(),
+                path: cookie.path.clone(),
+                secure: cookie.secure,
+                http_only: cookie.http_only,
+            })
+            .collect();
+        tab.call_method(cdp::SetCookies { cookies })?;
+    }
+
+    if let Some(login) = &config.login {
+        // With baselines, the trusted domain is never loaded.
+        let domains = if config.baselines.is_some() {
+            vec![&config.testing]
+        } else {
+            vec![&config.trusted, &config.testing]
+        };
+        for domain in domains {
+            log_in(tab, login, domain)
+                .map_err(|e| format!("Unable to log in to {}: {}", domain, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fills in and submits the login form on `domain`, then waits until logged in.
+fn log_in(tab: &Tab, login: &Login, domain: &str) -> Result<(), Box<dyn Error>> {
+    tab.navigate_to(&format!("{}{}", domain, login.route))?
+        .wait_until_navigated()?;
+    for field in &login.fields {
+        tab.wait_for_element(&field.selector)?.type_into(&field.value)?;
+    }
+    tab.wait_for_element(&login.submit)?.click()?;
+    tab.wait_for_element(&login.wait_for_selector)?;
+    Ok(())
+}
+
+/// Reads the user's stabilize script, if there is one.
+fn read_stabilize_script(config: &crate::config::Config) -> Result<Option<String>, Box<dyn Error>> {
+    match &config.stabilize_script {
+        Some(path) => Ok(Some(fs::read_to_string(path)?)),
+        None => Ok(None),
+    }
+}
+
+/// Settings that have to be in place before a tab navigates anywhere.
+/// Returns how many of the tab's requests have been blocked, or sent without headers, so far.
+fn prepare_tab(
+    tab: &Tab,
+    config: &crate::config::Config,
+    profile: &ViewportProfile,
+) -> Result<Arc<intercept::RequestCounts>, Box<dyn Error>> {
+    tab.set_default_timeout(Duration::from_secs(40));
+
+    if profile.emulates_device() {
+        tab.call_method(cdp::SetDeviceMetricsOverride {
+            width: profile.width,
+            height: 0,
+            device_scale_factor: profile.device_scale_factor,
+            mobile: profile.mobile,
+        })?;
+        tab.call_method(cdp::SetTouchEmulationEnabled {
+            enabled: profile.touch,
+            max_touch_points: if profile.touch { Some(5) } else { None },
+        })?;
+    }
+    if let Some(user_agent) = &profile.user_agent {
+        tab.set_user_agent(user_agent, None, None)?;
+    }
+
+    // Registered once, this runs before page scripts on every page the tab loads.
+    if let Some(source) = deterministic_script(config.freeze_time, config.random_seed) {
+        tab.call_method(cdp::AddScriptToEvaluateOnNewDocument { source })?;
+    }
+    if let Some(source) = readiness::request_tracking_script(config) {
+        tab.call_method(cdp::AddScriptToEvaluateOnNewDocument { source })?;
+    }
+    intercept::intercept_requests(tab, config)
+}
+
+/// Most window heights `scroll_through` goes down before giving up on reaching the bottom, so
+/// pages that keep growing as they are scrolled still get captured.
+const SCROLL_THROUGH_MAX_STEPS: u32 = 50;
+
+/// Longest `scroll_through` keeps scrolling, whatever the number of steps.
+const SCROLL_THROUGH_MAX_TIME: Duration = Duration::from_secs(60);
+
+/// Scrolls to window height number `step`, and waits up to 2 seconds for images to finish.
+/// Returns whether there is more page below.
+fn scroll_step_js(step: u32) -> String {
+    format!("async function() {{
+    const win = this.ownerDocument.defaultView;
+    const doc = this.ownerDocument;
+    const sleep = ms => new Promise(resolve => setTimeout(resolve, ms));
+    const imagesLoaded = () => Promise.all(Array.from(doc.images)
+        .filter(img => !img.complete)
+        .map(img => new Promise(resolve => {{
+            img.addEventListener(\"load\", resolve, {{ once: true }});
+            img.addEventListener(\"error\", resolve, {{ once: true }});
+        }})));
+    win.scrollTo(0, {} * Math.max(win.innerHeight, 1));
+    // Give observers a moment to start loading what came into view.
+    await sleep(50);
+    await Promise.race([imagesLoaded(), sleep(2000)]);
+    return win.scrollY + win.innerHeight < doc.documentElement.scrollHeight;
+}}", step)
+}
+
+/// Scrolls down the page one window height at a time, so lazy loaded images start loading.
+/// Each step is its own call, so tall pages don't run into the per-call timeout.
+/// Ends back at the top of the page.
+fn scroll_through(body: &Element) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    for step in 0..SCROLL_THROUGH_MAX_STEPS {
+        let more = body.call_js_fn(&scroll_step_js(step), true)?.value;
+        if more != Some(serde_json::Value::Bool(true)) || started.elapsed() >= SCROLL_THROUGH_MAX_TIME {
+            break;
+        }
+    }
+    body.call_js_fn("function() { this.ownerDocument.defaultView.scrollTo(0, 0); }", false)?;
+    Ok(())
+}
+
+/// Gets a loaded page ready for capture: scroll bar always shown, page stabilized, lazy content
+/// loaded if configured, and the mouse resting over the body.
+fn prepare_page(
+    tab: &Tab,
+    config: &crate::config::Config,
+    stylesheet: &str,
+    script: &Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let body = tab.wait_for_element("body")?;
+    body.call_js_fn("function() { this.style.overflowY = \"scroll\"; }", false)?;
+    stabilize(&body, stylesheet, script)?;
+    if config.scroll_through {
+        scroll_through(&body)?;
+    }
+    // move mouse to similar place on both of them,
+    // so that the trusted tab is forced to have a mouse hover,
+    // so that testing doesn't get focused elements that trusted url doesn't get
+    body.move_mouse_over()?;
+    Ok(())
+}
+
+/// Window height for a page `content_height` CSS pixels tall.
+///
+/// Growing the window very tall can make Chrome report a negative window `top`, which fails to
+/// parse, so with `tile_height` set the window is never made taller than one tile.
+fn window_height(config: &crate::config::Config, content_height: u32) -> u32 {
+    match config.tile_height {
+        Some(tile_height) if content_height > tile_height => tile_height,
+        _ => content_height + 1,
+    }
+}
+
+/// Screenshots the `viewport` area of the page as a PNG.
+///
+/// With `tile_height` set, pages taller than one tile are scrolled through and captured one tile
+/// at a time, and the tiles stitched together.
+fn capture_page(
+    tab: &Tab,
+    config: &crate::config::Config,
+    viewport: Viewport,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let tile_height = match config.tile_height {
+        Some(tile_height) if viewport.height > tile_height as f64 => tile_height,
+        _ => return Ok(tab.capture_screenshot(ScreenshotFormat::PNG, Some(viewport), true)?),
+    };
+
+    let mut tiles = vec![];
+    for (top, height) in tiles::tile_offsets(viewport.height.ceil() as u32, tile_height) {
+        // The clip is in page coordinates, but only what is scrolled into view gets drawn.
+        tab.evaluate(&format!("window.scrollTo(0, {})", viewport.y + top as f64), false)?;
+        let clip = Viewport {
+            y: viewport.y + top as f64,
+            height: height as f64,
+            ..viewport.clone()
+        };
+        tiles.push(tab.capture_screenshot(ScreenshotFormat::PNG, Some(clip), true)?);
+    }
+    tab.evaluate("window.scrollTo(0, 0)", false)?;
+    Ok(tiles::stitch(&tiles)?)
+}
+
+/// A screenshot of a page, or of one element on it, and the areas in it to ignore.
+struct Shot {
+    /// The `capture_selectors` entry the screenshot is clipped to, or `None` for the whole page.
+    selector: Option<String>,
+    /// Which of the elements matching `selector` this is, when it matched more than one.
+    index: Option<usize>,
+    png: Vec<u8>,
+    ignore: Vec<Rect>,
+}
+
+/// Screenshots the whole page in `page`, or with `capture_selectors`, every element matching
+/// each selector, clipped to its box model. `side` names the page in errors.
+fn take_shots(
+    tab: &Tab,
+    config: &crate::config::Config,
+    profile: &ViewportProfile,
+    page: Viewport,
+    capture_selectors: &[String],
+    ignore_selectors: &[String],
+    side: &str,
+) -> Result<Vec<Shot>, Box<dyn Error>> {
+    let scale = profile.device_scale_factor;
+    if capture_selectors.is_empty() {
+        let ignore = find_ignored_rects(tab, ignore_selectors, &page, scale);
+        return Ok(vec![Shot { selector: None, index: None, png: capture_page(tab, config, page)?, ignore }]);
+    }
+
+    let mut shots = vec![];
+    for selector in capture_selectors {
+        let elements = tab.find_elements(selector).unwrap_or_default();
+        if elements.is_empty() {
+            return Err(format!("No element matching \"{}\" to capture in the {} page", selector, side).into());
+        }
+        for (n, element) in elements.iter().enumerate() {
+            let index = Some(n).filter(|_| elements.len() > 1);
+            let clip = element.get_box_model()
+                .map_err(|_| format!("\"{}\" isn't rendered in the {} page", report::element_label(selector, index), side))?
+                .margin_viewport();
+            let ignore = find_ignored_rects(tab, ignore_selectors, &clip, scale);
+            shots.push(Shot { selector: Some(selector.clone()), index, png: capture_page(tab, config, clip)?, ignore });
+        }
+    }
+    Ok(shots)
+}
+
+/// The route being captured, and the viewport it is captured at.
+struct Route<'a> {
+    config: &'a crate::config::Config,
+    slug: &'a String,
+    profile: &'a ViewportProfile,
+}
+
+impl Route<'_> {
+    /// Base name of the screenshot files of the element at `index` of those matching `selector`,
+    /// or of the whole page.
+    fn pic_name(&self, selector: &Option<String>, index: Option<usize>) -> String {
+        match selector {
+            Some(selector) => url_utils::get_element_pic_name(self.slug, &self.profile.name, selector, index),
+            None => url_utils::get_pic_name(self.slug, &self.profile.name),
+        }
+    }
+}
+
+/// Pairs up the trusted and testing shots of the page or of each element. A selector that
+/// matches a different number of elements on the two pages can't be paired up.
+fn pair_shots(trusted_shots: Vec<Shot>, testing_shots: Vec<Shot>) -> Result<Vec<(Shot, Shot)>, Box<dyn Error>> {
+    for selector in trusted_shots.iter().chain(&testing_shots).filter_map(|shot| shot.selector.as_ref()) {
+        let matches = |shots: &[Shot]| shots.iter().filter(|shot| shot.selector.as_ref() == Some(selector)).count();
+        let (trusted, testing) = (matches(&trusted_shots), matches(&testing_shots));
+        if trusted != testing {
+            return Err(format!(
+                "\"{}\" matches {} elements in the trusted page but {} in the testing page",
+                selector, trusted, testing,
+            ).into());
+        }
+    }
+    Ok(trusted_shots.into_iter().zip(testing_shots).collect())
+}
+
+/// Writes the trusted and testing screenshots of each shot to the screenshots folder, and pairs
+/// them up for comparison.
+fn save_captures(
+    route: &Route,
+    trusted_shots: Vec<Shot>,
+    testing_shots: Vec<Shot>,
+    blocked: usize,
+    log: &mut RouteLog,
+) -> Result<Vec<Capture>, Box<dyn Error>> {
+    let mut captures = vec![];
+    for (trusted, testing) in pair_shots(trusted_shots, testing_shots)? {
+        let pic_name = route.pic_name(&testing.selector, testing.index);
+        log.println(format_args!("pic lengths: {:?} and {:?}", trusted.png.len(), testing.png.len()));
+        fs::write(format!("{}/{}_trusted.png", route.config.screenshots, pic_name), &trusted.png)?;
+        fs::write(format!("{}/{}_testing.png", route.config.screenshots, pic_name), &testing.png)?;
+
+        let images_identical = trusted.png.len() == testing.png.len() &&
+            md5::compute(&trusted.png) == md5::compute(&testing.png);
+        captures.push(Capture {
+            selector: testing.selector,
+            index: testing.index,
+            pic_name,
+            images_identical,
+            trusted_ignore: trusted.ignore,
+            testing_ignore: testing.ignore,
+            blocked: if captures.is_empty() { blocked } else { 0 },
+        });
+    }
+    Ok(captures)
+}
+
+/// Reports how many requests the trusted and testing pages of a route made that were blocked,
+/// or sent without the configured headers, and returns the number blocked.
+fn log_request_counts(log: &mut RouteLog, counts: &[&intercept::RequestCounts]) -> usize {
+    let blocked = counts.iter().map(|counts| counts.blocked.load(Ordering::SeqCst)).sum();
+    if blocked > 0 {
+        log.println(format_args!("blocked {} requests", blocked));
+    }
+    let without_headers: usize = counts.iter().map(|counts| counts.without_headers.load(Ordering::SeqCst)).sum();
+    if without_headers > 0 {
+        log.println(format!("sent {} requests without the configured headers", without_headers).yellow());
+    }
+    blocked
+}
+
+/// Removes the screenshots of `route` left by an earlier run, so a capture that fails part way
+/// doesn't leave old ones behind to be reported or approved as this run's.
+fn remove_old_screenshots(route: &Route) -> Result<(), Box<dyn Error>> {
+    let capture_selectors = route.config.capture_selectors_for(route.slug);
+    let mut pic_names = vec![];
+    if capture_selectors.is_empty() {
+        pic_names.push(route.pic_name(&None, None));
+    }
+    for selector in capture_selectors {
+        let pic_name = route.pic_name(&Some(selector), None);
+        for side in &["trusted", "testing", "diff"] {
+            let suffix = format!("_{}.png", side);
+            pic_names.extend(url_utils::find_indexed_pic_names(&route.config.screenshots, &pic_name, &suffix));
+        }
+        pic_names.push(pic_name);
+    }
+    for pic_name in pic_names {
+        for side in &["trusted", "testing", "diff"] {
+            match fs::remove_file(format!("{}/{}_{}.png", route.config.screenshots, pic_name, side)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Captures the trusted and testing versions of `slug` at `profile` with browsers from `pool`.
+/// There is one capture for the whole page, or one for each element matching the route's
+/// `capture_selectors`.
 pub fn capture_snapshots(
     config: &crate::config::Config,
     slug: &String,
-) -> Result<bool, Box<dyn Error>> {
-    let trusted_domain = &config.trusted;
-    let testing_domain = &config.testing;
+    profile: &ViewportProfile,
+    pool: &BrowserPool,
+    log: &mut RouteLog,
+) -> Result<Vec<Capture>, Box<dyn Error>> {
+    remove_old_screenshots(&Route { config, slug, profile })?;
+    if config.baselines.is_some() {
+        pool.with_tab(|tab| capture_against_baselines(config, slug, profile, tab, log))
+    } else if config.simultaneous {
+        pool.with_tab_pair(|trusted_tab, testing_tab| {
+            capture_side_by_side(config, slug, profile, trusted_tab, testing_tab, log)
+        })
+    } else {
+        pool.with_tab(|tab| capture_in_tab(config, slug, profile, tab, log))
+    }
+}
 
-    // We make a new browser per test, because a given browser stops responding
-    // after about 20-23 tests.
-    // Maybe there's a cleanup bug and tabs are not properly closed?
-    // It would be more efficient to reuse the same browser for the whole suite.
+/// Captures only the testing page, and uses the approved screenshots in `baselines` in place of
+/// the trusted page.
+///
+/// Testing screenshots without a baseline are still saved, so they can be approved.
+fn capture_against_baselines(
+    config: &crate::config::Config,
+    slug: &String,
+    profile: &ViewportProfile,
+    tab: &Tab,
+    log: &mut RouteLog,
+) -> Result<Vec<Capture>, Box<dyn Error>> {
+    let baselines = config.baselines.clone().unwrap_or_default();
+    let capture_selectors = config.capture_selectors_for(slug);
+    let ignore_selectors = config.ignore_selectors_for(slug);
+    let stylesheet = stabilize_stylesheet(&config.hide_selectors, config.disable_animations);
+    let script = read_stabilize_script(config)?;
+
+    log.println("testing url...".blue().dimmed());
+    let requests = prepare_tab(tab, config, profile)?;
+    tab.navigate_to(&(config.testing.clone() + slug))?
+        .wait_until_navigated()?;
+    tab.set_bounds(Bounds::Normal {
+        left: Some(0),
+        top: Some(0),
+        width: Some(profile.width),
+        height: None,
+    })?;
+    prepare_page(tab, config, &stylesheet, &script)?;
 
-    let browser = make_browser(config)?;
-    let pic_name = url_utils::get_name_from_slug(&slug);
+    let content_size = tab.wait_for_element("html")?.get_box_model()?;
+    tab.set_bounds(Bounds::Normal {
+        left: None,
+        top: None,
+        width: None,
+        height: Some(window_height(config, content_size.height)),
+    })?;
+    wait_for_render(&[tab], config, content_size.width * content_size.height, log)?;
 
-    let filepath_trusted = format!("{}/{}_trusted.png", config.screenshots, pic_name);
-    let filepath_testing = format!("{}/{}_testing.png", config.screenshots, pic_name);
+    log.println("capturing image...");
+    let viewport = content_size.margin_viewport();
+    let testing_shots = take_shots(tab, config, profile, viewport, &capture_selectors, &ignore_selectors, "testing")?;
+    let blocked = log_request_counts(log, &[&requests]);
+
+    let mut baseline_shots = vec![];
+    let mut missing = vec![];
+    let route = Route { config, slug, profile };
+    for testing in &testing_shots {
+        let pic_name = route.pic_name(&testing.selector, testing.index);
+        let baseline_path = url_utils::get_baseline_path(&baselines, &pic_name);
+        match fs::read(&baseline_path) {
+            // The baseline's own ignored areas aren't known, but should be where they are now.
+            Ok(png) => baseline_shots.push(Shot {
+                selector: testing.selector.clone(),
+                index: testing.index,
+                png,
+                ignore: testing.ignore.clone(),
+            }),
+            Err(_) => {
+                fs::write(format!("{}/{}_testing.png", config.screenshots, pic_name), &testing.png)?;
+                missing.push(baseline_path);
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "No baseline at \"{}\". Run \"nitpx approve\" to approve the testing screenshot",
+            missing.join("\", \""),
+        ).into());
+    }
 
-    println!("{}", "trusted url...".blue().dimmed());
-    let tab = browser.new_tab()?;
-    tab.set_default_timeout(Duration::from_secs(40));
+    save_captures(&route, baseline_shots, testing_shots, blocked, log)
+}
+
+/// Loads the trusted and testing pages at the same time in two tabs, and captures them right
+/// after each other, so content that changes over time has as little time as possible to drift.
+///
+/// Both tabs get the same bounds, mouse position and render wait.
+fn capture_side_by_side(
+    config: &crate::config::Config,
+    slug: &String,
+    profile: &ViewportProfile,
+    trusted_tab: &Tab,
+    testing_tab: &Tab,
+    log: &mut RouteLog,
+) -> Result<Vec<Capture>, Box<dyn Error>> {
+    let capture_selectors = config.capture_selectors_for(slug);
+    let ignore_selectors = config.ignore_selectors_for(slug);
+    let stylesheet = stabilize_stylesheet(&config.hide_selectors, config.disable_animations);
+    let script = read_stabilize_script(config)?;
+    let tabs = [trusted_tab, testing_tab];
+
+    log.println("trusted and testing urls...".blue().dimmed());
+    let mut requests = vec![];
+    for tab in &tabs {
+        requests.push(prepare_tab(tab, config, profile)?);
+    }
+    // Navigation doesn't block, so both pages load at once.
+    trusted_tab.navigate_to(&(config.trusted.clone() + slug))?;
+    testing_tab.navigate_to(&(config.testing.clone() + slug))?;
+
+    for tab in &tabs {
+        tab.wait_until_navigated()?;
+        tab.set_bounds(Bounds::Normal {
+            left: Some(0),
+            top: Some(0),
+            width: Some(profile.width),
+            height: None,
+        })?;
+        prepare_page(tab, config, &stylesheet, &script)?;
+    }
+
+    let mut content_sizes = vec![];
+    for tab in &tabs {
+        content_sizes.push(tab.wait_for_element("html")?.get_box_model()?);
+    }
+    let height = content_sizes.iter().map(|content_size| content_size.height).max().unwrap_or(0);
+    for tab in &tabs {
+        tab.set_bounds(Bounds::Normal {
+            left: None,
+            top: None,
+            width: None,
+            height: Some(window_height(config, height)),
+        })?;
+    }
+
+    let largest_px = content_sizes.iter()
+        .map(|content_size| content_size.width * content_size.height)
+        .max()
+        .unwrap_or(0);
+    wait_for_render(&tabs, config, largest_px, log)?;
+
+    log.println("capturing images...");
+    let mut shots = vec![];
+    for ((tab, content_size), side) in tabs.iter().zip(&content_sizes).zip(&["trusted", "testing"]) {
+        let viewport = content_size.margin_viewport();
+        shots.push(take_shots(tab, config, profile, viewport, &capture_selectors, &ignore_selectors, side)?);
+    }
+
+    let blocked = log_request_counts(log, &requests.iter().map(|counts| counts.as_ref()).collect::<Vec<_>>());
+
+    let testing_shots = shots.pop().unwrap_or_default();
+    let trusted_shots = shots.pop().unwrap_or_default();
+    save_captures(&Route { config, slug, profile }, trusted_shots, testing_shots, blocked, log)
+}
+
+fn capture_in_tab(
+    config: &crate::config::Config,
+    slug: &String,
+    profile: &ViewportProfile,
+    tab: &Tab,
+    log: &mut RouteLog,
+) -> Result<Vec<Capture>, Box<dyn Error>> {
+    let trusted_domain = &config.trusted;
+    let testing_domain = &config.testing;
+    let capture_selectors = config.capture_selectors_for(slug);
+    let ignore_selectors = config.ignore_selectors_for(slug);
+    let stylesheet = stabilize_stylesheet(&config.hide_selectors, config.disable_animations);
+    let script = read_stabilize_script(config)?;
+
+    log.println("trusted url...".blue().dimmed());
+    let requests = prepare_tab(tab, config, profile)?;
 
     tab.navigate_to(&(trusted_domain.clone() + &slug))?
         .wait_until_navigated()?;
@@ -69,13 +848,11 @@ pub fn capture_snapshots(
     tab.set_bounds(Bounds::Normal {
         left: Some(0),
         top: Some(0),
-        width: Some(1600),
+        width: Some(profile.width),
         height: None,
     })?;
 
-    let body = tab.wait_for_element("body")?;
-    body.call_js_fn("function() { this.style.overflowY = \"scroll\"; }", false)?;
-    body.move_mouse_over()?;
+    prepare_page(tab, config, &stylesheet, &script)?;
 
 
     let content_size = tab.wait_for_element("html")?
@@ -87,24 +864,19 @@ pub fn capture_snapshots(
         left: None,
         top: None,
         width: None,
-        height: Some(content_size.height + 1)
+        height: Some(window_height(config, content_size.height))
     })?;
 
     // TODO: Clean up code that insures equal scroll bar presence/ page widths.
 
-    let estimated_render_time = calculate_render_sleep(&(content_size.width * content_size.height));
+    let px_in_capture = content_size.width * content_size.height;
+    wait_for_render(&[tab], config, px_in_capture, log)?;
 
-    println!("wait {:?} for render...", estimated_render_time);
-    sleep(estimated_render_time);
+    log.println(format_args!("viewport: w: {:?} h: {:?}", viewport.width, viewport.height));
+    log.println("capturing image...");
+    let trusted_shots = take_shots(tab, config, profile, viewport, &capture_selectors, &ignore_selectors, "trusted")?;
 
-    println!("viewport: w: {:?} h: {:?}", viewport.width, viewport.height);
-    println!("capturing image...");
-    let pic_trusted = tab.capture_screenshot(ScreenshotFormat::PNG, Some(viewport ), true)?;
-    let pic_trusted_len = pic_trusted.len();
 
-    println!("pic length: {:?}", pic_trusted_len);
-    let mut out_trusted = File::create(filepath_trusted)?;
-    out_trusted.write(&pic_trusted)?;
 
 
 
@@ -112,35 +884,29 @@ pub fn capture_snapshots(
 
 
 
-
-
-    println!("{}", "testing url...".blue().dimmed());
+    log.println("testing url...".blue().dimmed());
     tab.navigate_to(&(testing_domain.clone() + &slug))?
         .wait_until_navigated()?;
 
-    let body = tab.wait_for_element("body")?;
-    body.call_js_fn("function() { this.style.overflowY = \"scroll\"; }", false)?;
-    // move mouse to similar place on both of them,
-    // so that the trusted tab is forced to have a mouse hover,
-    // so that testing doesn't get focused elements that trusted url doesn't get
-    body.move_mouse_over()?;
+    prepare_page(tab, config, &stylesheet, &script)?;
 
-    println!("setting bounds");
+    log.println("setting bounds");
     tab.set_bounds(Bounds::Normal {
         left: None,
         top: Some(0),
-        width: Some(1600),
+        width: Some(profile.width),
         height: None,
     })?;
 
-    println!("getting html box model");
+    log.println("getting html box model");
     let content_size = tab.wait_for_element("html")?
         .get_box_model()?;
     let viewport = content_size.margin_viewport();
-    println!("viewport: w: {:?} h: {:?}", viewport.width, viewport.height);
+    log.println(format_args!("viewport: w: {:?} h: {:?}", viewport.width, viewport.height));
     // To really be sure and get true full snapshots this should probably set_bounds again here.
     // but the getWindowForTarget can return negative top when you grow the top a lot, and if you do
     // that the CurrentBounds type asplodes in parsing. So don't do that...
+    // Use tile_height to capture very tall pages without growing the window.
 
     // e.g.
     // method_call MethodCall { method_name: "Browser.getWindowForTarget", id: 12, params: GetWindowForTarget { target_id: "D27B318B1E2A43F837C7D05370C72ACD" } }
@@ -148,21 +914,12 @@ pub fn capture_snapshots(
     // "{\"method\":\"Browser.getWindowForTarget\",\"id\":12,\"params\":{\"targetId\":\"D27B318B1E2A43F837C7D05370C72ACD\"}}"
     // thing_to_parse: Object({"bounds": Object({"height": Number(5201), "left": Number(0), "top": Number(-4001), "width": Number(1600), "windowState": String("normal")}), "windowId": Number(1)})
 
-    println!("waiting {:?} for render...", estimated_render_time);
-    sleep(estimated_render_time);
-    println!("capturing image...");
-    let pic_testing = tab.capture_screenshot(ScreenshotFormat::PNG, Some(viewport), true)?;
-    let pic_testing_len = pic_testing.len();
-
-    println!("pic length: {:?}", pic_testing_len);
-    let mut out_testing = File::create(filepath_testing)?;
-    out_testing.write(&pic_testing)?;
-
-
-    let images_are_same = pic_trusted_len == pic_testing_len &&
-        md5::compute(pic_trusted) == md5::compute(pic_testing);
+    wait_for_render(&[tab], config, px_in_capture, log)?;
+    log.println("capturing image...");
+    let testing_shots = take_shots(tab, config, profile, viewport, &capture_selectors, &ignore_selectors, "testing")?;
+    let blocked = log_request_counts(log, &[&requests]);
 
-    Ok(images_are_same)
+    save_captures(&Route { config, slug, profile }, trusted_shots, testing_shots, blocked, log)
 }
 
 #[cfg(test)]
@@ -171,6 +928,57 @@ mod tests {
 
     #[test]
     fn sleeps_one_second_per_ten_million_px() {
-        assert_eq!(calculate_render_sleep(&10_000_000), Duration::from_secs(1));
+        let no_base = Duration::from_millis(0);
+        let per_megapixel = Duration::from_millis(100);
+        assert_eq!(calculate_render_sleep(&10_000_000, no_base, per_megapixel), Duration::from_secs(1));
+        assert_eq!(
+            calculate_render_sleep(&10_000_000, Duration::from_millis(30), per_megapixel),
+            Duration::from_millis(1030),
+        );
+    }
+
+    #[test]
+    fn stylesheet_hides_each_selector_separately() {
+        let selectors = vec![String::from(".ad"), String::from("#clock")];
+        assert_eq!(
+            stabilize_stylesheet(&selectors, false),
+            ".ad { visibility: hidden !important; }\n#clock { visibility: hidden !important; }",
+        );
+        assert_eq!(stabilize_stylesheet(&[], false), "");
+        assert!(stabilize_stylesheet(&[], true).contains("animation: none !important"));
+    }
+
+    #[test]
+    fn deterministic_script_only_patches_what_is_configured() {
+        assert_eq!(deterministic_script(None, None), None);
+
+        let time_only = deterministic_script(Some(1_577_836_800_000), None).unwrap();
+        assert!(time_only.contains("var frozenTime = 1577836800000;"));
+        assert!(!time_only.contains("Math.random"));
+
+        let seed_only = deterministic_script(None, Some(42)).unwrap();
+        assert!(seed_only.contains("var state = 42;"));
+        assert!(!seed_only.contains("frozenTime"));
+    }
+
+    #[test]
+    fn shots_pair_up_only_when_each_selector_matches_as_many_elements() {
+        let shots = |selector: &str, count: usize| -> Vec<Shot> {
+            (0..count)
+                .map(|n| Shot {
+                    selector: Some(selector.to_string()),
+                    index: Some(n).filter(|_| count > 1),
+                    png: vec![],
+                    ignore: vec![],
+                })
+                .collect()
+        };
+
+        let pairs = pair_shots(shots("li", 3), shots("li", 3)).unwrap();
+        let indexes: Vec<_> = pairs.iter().map(|(trusted, testing)| (trusted.index, testing.index)).collect();
+        assert_eq!(indexes, vec![(Some(0), Some(0)), (Some(1), Some(1)), (Some(2), Some(2))]);
+
+        let err = pair_shots(shots("li", 3), shots("li", 2)).err().map(|e| e.to_string());
+        assert_eq!(err.as_deref(), Some("\"li\" matches 3 elements in the trusted page but 2 in the testing page"));
     }
 }
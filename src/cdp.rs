@@ -0,0 +1,89 @@
+//! Chrome DevTools Protocol methods that `headless_chrome` doesn't wrap yet.
+//!
+//! Call them with `Tab::call_method`.
+
+use headless_chrome::protocol::Method;
+
+/// Runs `source` in every new document of the tab, before any of the page's own scripts.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AddScriptToEvaluateOnNewDocument {
+    pub source: String,
+}
+/// The returned script identifier is not needed, since scripts are never removed.
+#[derive(Debug, Deserialize)]
+pub struct AddScriptToEvaluateOnNewDocumentReturnObject {}
+impl Method for AddScriptToEvaluateOnNewDocument {
+    const NAME: &'static str = "Page.addScriptToEvaluateOnNewDocument";
+    type ReturnObject = AddScriptToEvaluateOnNewDocumentReturnObject;
+}
+
+/// Closes the tab with `target_id`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CloseTarget {
+    pub target_id: String,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloseTargetReturnObject {
+    pub success: bool,
+}
+impl Method for CloseTarget {
+    const NAME: &'static str = "Target.closeTarget";
+    type ReturnObject = CloseTargetReturnObject;
+}
+
+/// A cookie for the domain of `url`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieParam {
+    pub name: String,
+    pub value: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+/// Sets cookies for the whole browser, so every tab sees them.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetCookies {
+    pub cookies: Vec<CookieParam>,
+}
+impl Method for SetCookies {
+    const NAME: &'static str = "Network.setCookies";
+    type ReturnObject = EmptyReturnObject;
+}
+
+/// Emulates a device's screen. A `width` or `height` of 0 leaves that dimension to the window.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDeviceMetricsOverride {
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    pub mobile: bool,
+}
+impl Method for SetDeviceMetricsOverride {
+    const NAME: &'static str = "Emulation.setDeviceMetricsOverride";
+    type ReturnObject = EmptyReturnObject;
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetTouchEmulationEnabled {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_touch_points: Option<u32>,
+}
+impl Method for SetTouchEmulationEnabled {
+    const NAME: &'static str = "Emulation.setTouchEmulationEnabled";
+    type ReturnObject = EmptyReturnObject;
+}
+
+/// For methods that return nothing.
+#[derive(Debug, Deserialize)]
+pub struct EmptyReturnObject {}
@@ -0,0 +1,234 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::Rgba;
+
+/// How the difference between two pixels is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMetric {
+    /// Sum of the absolute red, green and blue channel differences.
+    #[default]
+    Sum,
+    /// Perceptual difference in the YIQ color space, as used by pixelmatch.
+    Yiq,
+    /// CIE ΔE2000 difference in the CIELAB color space.
+    Ciede2000,
+}
+
+impl fmt::Display for DiffMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DiffMetric::Sum => "sum",
+            DiffMetric::Yiq => "yiq",
+            DiffMetric::Ciede2000 => "ciede2000",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for DiffMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiffMetric, String> {
+        match s.trim().to_lowercase().as_str() {
+            "sum" => Ok(DiffMetric::Sum),
+            "yiq" => Ok(DiffMetric::Yiq),
+            "ciede2000" => Ok(DiffMetric::Ciede2000),
+            other => Err(format!("Unknown diff metric \"{}\". Expected one of sum, yiq, ciede2000", other)),
+        }
+    }
+}
+
+/// Largest possible YIQ delta, between black and white.
+const MAX_YIQ_DELTA: f64 = 35215.0;
+
+/// ΔE2000 of 100 is roughly the distance between black and white.
+const MAX_DELTA_E: f64 = 100.0;
+
+/// Composites a possibly transparent pixel over a white background.
+fn blend_white(rgba: Rgba<u8>) -> [f64; 3] {
+    let alpha = rgba[3] as f64 / 255.0;
+    [
+        255.0 + (rgba[0] as f64 - 255.0) * alpha,
+        255.0 + (rgba[1] as f64 - 255.0) * alpha,
+        255.0 + (rgba[2] as f64 - 255.0) * alpha,
+    ]
+}
+
+fn rgb_to_y(rgb: [f64; 3]) -> f64 {
+    rgb[0] * 0.298_895_31 + rgb[1] * 0.586_622_47 + rgb[2] * 0.114_482_23
+}
+
+fn rgb_to_i(rgb: [f64; 3]) -> f64 {
+    rgb[0] * 0.595_977_99 - rgb[1] * 0.274_176_10 - rgb[2] * 0.321_801_89
+}
+
+fn rgb_to_q(rgb: [f64; 3]) -> f64 {
+    rgb[0] * 0.211_470_17 - rgb[1] * 0.522_617_11 + rgb[2] * 0.311_146_94
+}
+
+/// Brightness of a pixel composited over white, from 0 to 255.
+pub(crate) fn brightness(rgba: Rgba<u8>) -> f64 {
+    rgb_to_y(blend_white(rgba))
+}
+
+fn sum_delta(rgb1: [f64; 3], rgb2: [f64; 3]) -> f64 {
+    ((rgb1[0] - rgb2[0]).abs() + (rgb1[1] - rgb2[1]).abs() + (rgb1[2] - rgb2[2]).abs()) / (255.0 * 3.0)
+}
+
+fn yiq_delta(rgb1: [f64; 3], rgb2: [f64; 3]) -> f64 {
+    let y = rgb_to_y(rgb1) - rgb_to_y(rgb2);
+    let i = rgb_to_i(rgb1) - rgb_to_i(rgb2);
+    let q = rgb_to_q(rgb1) - rgb_to_q(rgb2);
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_YIQ_DELTA
+}
+
+fn srgb_to_linear(channel: f64) -> f64 {
+    let c = channel / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts an sRGB color to CIELAB using the D65 white point.
+fn rgb_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    let r = srgb_to_linear(rgb[0]);
+    let g = srgb_to_linear(rgb[1]);
+    let b = srgb_to_linear(rgb[2]);
+
+    let x = (r * 0.412_456_4 + g * 0.357_576_1 + b * 0.180_437_5) / 0.950_47;
+    let y = r * 0.212_672_9 + g * 0.715_152_2 + b * 0.072_175_0;
+    let z = (r * 0.019_333_9 + g * 0.119_192_0 + b * 0.950_304_1) / 1.088_83;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIE ΔE2000 color difference between two CIELAB colors.
+pub fn ciede2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let c_bar7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt());
+
+    let a1p = a1 * (1.0 + g);
+    let a2p = a2 * (1.0 + g);
+    let c1p = (a1p * a1p + b1 * b1).sqrt();
+    let c2p = (a2p * a2p + b2 * b2).sqrt();
+
+    let hue = |b: f64, a: f64| {
+        if b == 0.0 && a == 0.0 {
+            0.0
+        } else {
+            let h = b.atan2(a).to_degrees();
+            if h < 0.0 { h + 360.0 } else { h }
+        }
+    };
+    let h1p = hue(b1, a1p);
+    let h2p = hue(b2, a2p);
+
+    let dl = l2 - l1;
+    let dc = c2p - c1p;
+    let dh = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dh_big = 2.0 * (c1p * c2p).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar_p = (c1p + c2p) / 2.0;
+    let h_bar_p = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar_p - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar_p).to_radians().cos()
+        + 0.32 * (3.0 * h_bar_p + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar_p - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_bar_p - 275.0) / 25.0).powi(2)).exp();
+    let c_bar_p7 = c_bar_p.powi(7);
+    let r_c = 2.0 * (c_bar_p7 / (c_bar_p7 + 25f64.powi(7))).sqrt();
+    let l_offset = (l_bar - 50.0).powi(2);
+    let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar_p;
+    let s_h = 1.0 + 0.015 * c_bar_p * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    let l_term = dl / s_l;
+    let c_term = dc / s_c;
+    let h_term = dh_big / s_h;
+    (l_term * l_term + c_term * c_term + h_term * h_term + r_t * c_term * h_term).sqrt()
+}
+
+fn ciede2000_delta(rgb1: [f64; 3], rgb2: [f64; 3]) -> f64 {
+    (ciede2000(rgb_to_lab(rgb1), rgb_to_lab(rgb2)) / MAX_DELTA_E).min(1.0)
+}
+
+/// Difference between two pixels under `metric`, from 0 (identical) to 1 (black vs white).
+///
+/// Transparent pixels are composited over white first.
+pub fn pixel_delta(metric: DiffMetric, rgba1: Rgba<u8>, rgba2: Rgba<u8>) -> f64 {
+    if rgba1 == rgba2 {
+        return 0.0;
+    }
+    let rgb1 = blend_white(rgba1);
+    let rgb2 = blend_white(rgba2);
+    match metric {
+        DiffMetric::Sum => sum_delta(rgb1, rgb2),
+        DiffMetric::Yiq => yiq_delta(rgb1, rgb2),
+        DiffMetric::Ciede2000 => ciede2000_delta(rgb1, rgb2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn black_and_white_are_nearly_maximally_different() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        for metric in &[DiffMetric::Sum, DiffMetric::Yiq, DiffMetric::Ciede2000] {
+            assert!(pixel_delta(*metric, black, white) > 0.9, "{}", metric);
+            assert_eq!(pixel_delta(*metric, black, black), 0.0);
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_are_blended_over_white() {
+        let white = Rgba([255, 255, 255, 255]);
+        assert_eq!(pixel_delta(DiffMetric::Sum, Rgba([0, 0, 0, 0]), white), 0.0);
+    }
+
+    #[test]
+    fn ciede2000_matches_reference_data() {
+        // From Sharma, Wu and Dalal's CIEDE2000 test data.
+        let delta = ciede2000([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485]);
+        assert!((delta - 2.0425).abs() < 0.0001);
+    }
+}
@@ -1,5 +1,5 @@
 use std::env;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use url::Url;
 use directories::ProjectDirs;
 use std::fs::File;
@@ -8,6 +8,10 @@ use std::io::{BufReader, Read};
 use serde;
 use serde_json;
 
+use crate::color::DiffMetric;
+use crate::regions::Rect;
+use crate::report::Reporter;
+
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub struct CliConfig {
     pub config: Option<String>,
@@ -18,36 +22,201 @@ pub struct CliConfig {
     pub threshold: Option<String>,
     pub ignored: Option<String>,
     pub routes: Option<String>,
+    pub metric: Option<String>,
+    pub tolerance: Option<String>,
+    pub ignore_antialiasing: bool,
+    pub align_rows: bool,
+    pub ignore_selectors: Option<String>,
+    pub capture_selectors: Option<String>,
+    pub hide_selectors: Option<String>,
+    pub disable_animations: bool,
+    pub stabilize_script: Option<String>,
+    pub freeze_time: Option<String>,
+    pub random_seed: Option<String>,
+    pub max_browser_uses: Option<String>,
+    pub concurrency: Option<String>,
+    pub simultaneous: bool,
+    pub render_wait_base_ms: Option<String>,
+    pub render_wait_per_megapixel_ms: Option<String>,
+    pub wait_for_network_idle_ms: Option<String>,
+    pub wait_for_fonts: bool,
+    pub wait_for_images: bool,
+    pub wait_for_selector: Option<String>,
+    pub wait_for_selector_gone: Option<String>,
+    pub wait_for_script: Option<String>,
+    pub scroll_through: bool,
+    pub tile_height: Option<String>,
+    pub block_requests: Option<String>,
+    pub summary_json: Option<String>,
+    pub reporter: Option<String>,
+    pub report_path: Option<String>,
+    pub baselines: Option<String>,
+    /// Set for subcommands that only diff image files, which don't need the trusted and testing
+    /// domains.
+    pub offline: bool,
 }
 
 pub fn config_to_env(config: &Config) -> String {
     format!("
+export NITPX_ALIGN_ROWS=\"{}\"
+export NITPX_BASELINES=\"{}\"
+export NITPX_BLOCK_REQUESTS=\"{}\"
+export NITPX_CAPTURE_SELECTORS=\"{}\"
+export NITPX_CONCURRENCY=\"{}\"
+export NITPX_DISABLE_ANIMATIONS=\"{}\"
+export NITPX_FREEZE_TIME=\"{}\"
 export NITPX_HEADLESS=\"{}\"
+export NITPX_HIDE_SELECTORS=\"{}\"
 export NITPX_IGNORED=\"{}\"
+export NITPX_IGNORE_SELECTORS=\"{}\"
+export NITPX_IGNORE_ANTIALIASING=\"{}\"
+export NITPX_MAX_BROWSER_USES=\"{}\"
+export NITPX_METRIC=\"{}\"
+export NITPX_RANDOM_SEED=\"{}\"
+export NITPX_RENDER_WAIT_BASE_MS=\"{}\"
+export NITPX_RENDER_WAIT_PER_MEGAPIXEL_MS=\"{}\"
+export NITPX_REPORTER=\"{}\"
+export NITPX_REPORT_PATH=\"{}\"
 export NITPX_ROUTES=\"{}\"
 export NITPX_SCREENSHOTS=\"{}\"
+export NITPX_SCROLL_THROUGH=\"{}\"
+export NITPX_SIMULTANEOUS=\"{}\"
+export NITPX_STABILIZE_SCRIPT=\"{}\"
+export NITPX_SUMMARY_JSON=\"{}\"
 export NITPX_TESTING=\"{}\"
 export NITPX_THRESHOLD=\"{}\"
-export NITPX_TRUSTED=\"{}\"",
+export NITPX_TILE_HEIGHT=\"{}\"
+export NITPX_TOLERANCE=\"{}\"
+export NITPX_TRUSTED=\"{}\"
+export NITPX_WAIT_FOR_FONTS=\"{}\"
+export NITPX_WAIT_FOR_IMAGES=\"{}\"
+export NITPX_WAIT_FOR_NETWORK_IDLE_MS=\"{}\"
+export NITPX_WAIT_FOR_SCRIPT=\"{}\"
+export NITPX_WAIT_FOR_SELECTOR=\"{}\"
+export NITPX_WAIT_FOR_SELECTOR_GONE=\"{}\"",
+        config.align_rows,
+        config.baselines.clone().unwrap_or_default(),
+        config.block_requests.join(","),
+        config.capture_selectors.join(","),
+        config.concurrency,
+        config.disable_animations,
+        config.freeze_time.map(|x| x.to_string()).unwrap_or_default(),
         config.headless,
+        config.hide_selectors.join(","),
         config.ignored.iter().map(|x| x.clone()).collect::<Vec<String>>().join(","),
+        config.ignore_selectors.join(","),
+        config.ignore_antialiasing,
+        config.max_browser_uses,
+        config.metric,
+        config.random_seed.map(|x| x.to_string()).unwrap_or_default(),
+        config.render_wait_base_ms,
+        config.render_wait_per_megapixel_ms,
+        config.reporter.map(|x| x.to_string()).unwrap_or_default(),
+        config.report_path.clone().unwrap_or_default(),
         config.routes,
         config.screenshots,
+        config.scroll_through,
+        config.simultaneous,
+        config.stabilize_script.clone().unwrap_or_default(),
+        config.summary_json.clone().unwrap_or_default(),
         config.testing,
         config.threshold.to_string(),
+        config.tile_height.map(|x| x.to_string()).unwrap_or_default(),
+        config.tolerance,
         config.trusted,
+        config.wait_for_fonts,
+        config.wait_for_images,
+        config.wait_for_network_idle_ms.map(|x| x.to_string()).unwrap_or_default(),
+        config.wait_for_script.clone().unwrap_or_default(),
+        config.wait_for_selector.clone().unwrap_or_default(),
+        config.wait_for_selector_gone.clone().unwrap_or_default(),
     )
 }
 
 pub fn config_to_flags(config: &Config) -> String {
-    format!("--headless {} --ignored {} --routes {} --screenshots {} --testing {} --threshold {} --trusted {}",
+    let align_rows = if config.align_rows { "--align-rows " } else { "" };
+    let disable_animations = if config.disable_animations { "--disable-animations " } else { "" };
+    let ignore_antialiasing = if config.ignore_antialiasing { " --ignore-antialiasing" } else { "" };
+    let scroll_through = if config.scroll_through { " --scroll-through" } else { "" };
+    let simultaneous = if config.simultaneous { " --simultaneous" } else { "" };
+    let stabilize_script = match &config.stabilize_script {
+        Some(path) => format!(" --stabilize-script {}", path),
+        None => String::from(""),
+    };
+    let freeze_time = match config.freeze_time {
+        Some(time) => format!("--freeze-time {} ", time),
+        None => String::from(""),
+    };
+    let random_seed = match config.random_seed {
+        Some(seed) => format!(" --random-seed {}", seed),
+        None => String::from(""),
+    };
+    let baselines = match &config.baselines {
+        Some(path) => format!("--baselines {} ", path),
+        None => String::from(""),
+    };
+    let reporter = match (config.reporter, &config.report_path) {
+        (Some(reporter), Some(path)) => format!(" --reporter {} --report-path {}", reporter, path),
+        _ => String::from(""),
+    };
+    let summary_json = match &config.summary_json {
+        Some(path) => format!(" --summary-json {}", path),
+        None => String::from(""),
+    };
+    let tile_height = match config.tile_height {
+        Some(height) => format!(" --tile-height {}", height),
+        None => String::from(""),
+    };
+    let mut wait_for = String::from("");
+    if config.wait_for_fonts {
+        wait_for.push_str(" --wait-for-fonts");
+    }
+    if config.wait_for_images {
+        wait_for.push_str(" --wait-for-images");
+    }
+    if let Some(idle_ms) = config.wait_for_network_idle_ms {
+        wait_for.push_str(&format!(" --wait-for-network-idle-ms {}", idle_ms));
+    }
+    if let Some(script) = &config.wait_for_script {
+        wait_for.push_str(&format!(" --wait-for-script '{}'", script));
+    }
+    if let Some(selector) = &config.wait_for_selector {
+        wait_for.push_str(&format!(" --wait-for-selector \"{}\"", selector));
+    }
+    if let Some(selector) = &config.wait_for_selector_gone {
+        wait_for.push_str(&format!(" --wait-for-selector-gone \"{}\"", selector));
+    }
+    format!("{}{}--block-requests \"{}\" --capture-selectors \"{}\" --concurrency {} {}{}--headless {} --hide-selectors \"{}\" --ignored {} --ignore-selectors \"{}\"{} --max-browser-uses {} --metric {}{} --render-wait-base-ms {} --render-wait-per-megapixel-ms {}{} --routes {} --screenshots {}{}{}{}{} --testing {} --threshold {}{} --tolerance {} --trusted {}{}",
+        align_rows,
+        baselines,
+        config.block_requests.join(","),
+        config.capture_selectors.join(","),
+        config.concurrency,
+        disable_animations,
+        freeze_time,
         config.headless,
+        config.hide_selectors.join(","),
         config.ignored.iter().map(|x| x.clone()).collect::<Vec<String>>().join(","),
+        config.ignore_selectors.join(","),
+        ignore_antialiasing,
+        config.max_browser_uses,
+        config.metric,
+        random_seed,
+        config.render_wait_base_ms,
+        config.render_wait_per_megapixel_ms,
+        reporter,
         config.routes,
         config.screenshots,
+        scroll_through,
+        simultaneous,
+        stabilize_script,
+        summary_json,
         config.testing,
         config.threshold.to_string(),
+        tile_height,
+        config.tolerance,
         config.trusted,
+        wait_for,
     )
 }
 
@@ -73,6 +242,116 @@ pub fn read_json_file<T: std::fmt::Debug + serde::de::DeserializeOwned + serde::
     parsed.ok()
 }
 
+/// Settings that replace the global config value for a single route.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteOverrides {
+    /// Replaces the global `threshold` for this route.
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    /// Added to the global `ignore_rects` for this route.
+    #[serde(default)]
+    pub ignore_rects: Vec<Rect>,
+    /// Added to the global `ignore_selectors` for this route.
+    #[serde(default)]
+    pub ignore_selectors: Vec<String>,
+    /// Added to the global `capture_selectors` for this route.
+    #[serde(default)]
+    pub capture_selectors: Vec<String>,
+}
+
+/// A named window width, and optionally a device, that every route is captured at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ViewportProfile {
+    /// Included in screenshot file names and the summary, e.g. `"mobile"`.
+    pub name: String,
+    /// Page width in CSS pixels.
+    pub width: u32,
+    /// Screenshot pixels per CSS pixel, e.g. 2 for a high density phone screen.
+    /// Defaults to 1.
+    #[serde(default = "default_device_scale_factor")]
+    pub device_scale_factor: f64,
+    /// When true, the page is rendered like on a phone or tablet, honoring the meta viewport tag.
+    /// Defaults to false.
+    #[serde(default)]
+    pub mobile: bool,
+    /// When true, the page sees a touch screen instead of a mouse.
+    /// Defaults to false.
+    #[serde(default)]
+    pub touch: bool,
+    /// Replaces the browser's user agent string.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+impl ViewportProfile {
+    /// Whether the screen has to be emulated, rather than just resizing the window.
+    pub fn emulates_device(&self) -> bool {
+        self.device_scale_factor != 1.0 || self.mobile || self.touch
+    }
+}
+
+fn default_device_scale_factor() -> f64 {
+    1.0
+}
+
+fn default_viewports() -> Vec<ViewportProfile> {
+    vec![ViewportProfile {
+        name: String::from("desktop"),
+        width: 1600,
+        device_scale_factor: 1.0,
+        mobile: false,
+        touch: false,
+        user_agent: None,
+    }]
+}
+
+/// A cookie set for one of the domains before any route is captured.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Defaults to the whole domain.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Defaults to false.
+    #[serde(default)]
+    pub secure: bool,
+    /// Defaults to false.
+    #[serde(default)]
+    pub http_only: bool,
+}
+
+/// Requests for URLs matching the glob `from` are redirected to `to`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RewriteRule {
+    /// E.g. `"https://testing.example.com/static/*"`.
+    pub from: String,
+    /// Each `*` is replaced by what the matching `*` in `from` matched,
+    /// e.g. `"https://cdn.example.com/static/*"`.
+    pub to: String,
+}
+
+/// A value typed into one field of the login form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginField {
+    /// CSS selector for the field, e.g. `"#email"`.
+    pub selector: String,
+    pub value: String,
+}
+
+/// A login form filled in and submitted on both domains whenever a browser is launched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Login {
+    /// Slug of the login page, e.g. `"login"`.
+    pub route: String,
+    /// Typed into the form in order.
+    pub fields: Vec<LoginField>,
+    /// CSS selector for the element clicked to submit the form.
+    pub submit: String,
+    /// CSS selector for an element that is only in the page once logged in.
+    pub wait_for_selector: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Useful for debugging. Also, some browsers have issues setting large window heights and
@@ -97,12 +376,242 @@ pub struct Config {
     /// test is considered to have failed.
     /// Defaults to 0 (no differences allowed).
     pub threshold: f64,
+    /// Per route settings, keyed by route slug.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub overrides: HashMap<String, RouteOverrides>,
+    /// How the difference between two pixels is measured: `"sum"`, `"yiq"` or `"ciede2000"`.
+    /// Defaults to `"sum"`, the sum of absolute RGB channel differences.
+    #[serde(default)]
+    pub metric: DiffMetric,
+    /// Float from 0 to 1 for how different a single pixel may be under `metric` and still count
+    /// as unchanged.
+    /// Defaults to 0 (any change counts).
+    #[serde(default)]
+    pub tolerance: f64,
+    /// Anti-aliased text and edges often render slightly differently between two captures.
+    /// When true, pixels that look like anti-aliasing are highlighted in yellow and not counted
+    /// toward the percent difference.
+    /// Defaults to false.
+    #[serde(default)]
+    pub ignore_antialiasing: bool,
+    /// When content is inserted or removed near the top of a page, everything below it moves
+    /// and shows up as changed.
+    /// When true, rows of both screenshots are aligned like lines in a text diff, so only the
+    /// inserted (blue) and removed (magenta) bands count as changed.
+    /// Defaults to false.
+    #[serde(default)]
+    pub align_rows: bool,
+    /// Areas in screenshot pixel coordinates, e.g. `{"x": 0, "y": 0, "width": 1600, "height": 90}`,
+    /// left out of every comparison. Useful for ads, timestamps and carousels.
+    /// They are drawn hatched in the diff image.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub ignore_rects: Vec<Rect>,
+    /// CSS selectors for elements left out of every comparison.
+    /// Matching elements are located separately in the trusted and testing pages.
+    #[serde(default)]
+    pub ignore_selectors: Vec<String>,
+    /// CSS selectors for elements to screenshot and compare on their own, instead of the whole
+    /// page. Each is clipped to the box model of the first matching element, and reported as
+    /// `route#selector`.
+    #[serde(default)]
+    pub capture_selectors: Vec<String>,
+    /// CSS selectors for elements hidden with `visibility: hidden` before capture, in both pages.
+    /// Unlike `ignore_selectors`, the elements keep their space but are not drawn, so anything
+    /// behind them is still compared.
+    #[serde(default)]
+    pub hide_selectors: Vec<String>,
+    /// When true, CSS animations and transitions are turned off and the text caret is hidden
+    /// before capture.
+    /// Defaults to false.
+    #[serde(default)]
+    pub disable_animations: bool,
+    /// Path to a JavaScript file run in both pages before the render wait, e.g. to pause videos
+    /// or close cookie banners.
+    /// It runs as the body of an async function, so it may `await`.
+    #[serde(default)]
+    pub stabilize_script: Option<String>,
+    /// Milliseconds since the Unix epoch that `Date.now()` and `new Date()` return in both pages,
+    /// so relative times like "posted 3 minutes ago" render the same.
+    /// The clock is frozen before any page script runs. Not frozen by default.
+    #[serde(default)]
+    pub freeze_time: Option<i64>,
+    /// Seed for a generator that replaces `Math.random` in both pages before any page script
+    /// runs, so shuffled content comes out in the same order.
+    /// Not seeded by default.
+    #[serde(default)]
+    pub random_seed: Option<u32>,
+    /// How many routes one browser captures before it is closed and a new one launched.
+    /// Browsers are shared across routes, and also relaunched whenever they stop responding.
+    /// Defaults to 50.
+    #[serde(default = "default_max_browser_uses")]
+    pub max_browser_uses: usize,
+    /// How many routes are captured and diffed at the same time, each in its own browser.
+    /// Output of each route is printed once it finishes, and the summary keeps route order.
+    /// Defaults to 1.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// When true, the trusted and testing pages are loaded at the same time in two tabs and
+    /// captured right after each other, instead of one after the other in a single tab.
+    /// Faster, and gives time dependent content less time to drift between captures.
+    /// Defaults to false.
+    #[serde(default)]
+    pub simultaneous: bool,
+    /// Every route is captured once per viewport.
+    /// Only read from the JSON config file.
+    /// Defaults to a single 1600 pixel wide `"desktop"` viewport.
+    #[serde(default = "default_viewports")]
+    pub viewports: Vec<ViewportProfile>,
+    /// Without any `wait_for_` conditions, pages are given a fixed time to render before capture:
+    /// this many milliseconds, plus `render_wait_per_megapixel_ms` for every million pixels.
+    /// Defaults to 30.
+    #[serde(default = "default_render_wait_base_ms")]
+    pub render_wait_base_ms: u64,
+    /// Milliseconds of render time per million pixels of page. Defaults to 100.
+    #[serde(default = "default_render_wait_per_megapixel_ms")]
+    pub render_wait_per_megapixel_ms: u64,
+    /// Wait until the page has loaded, no fetch or XHR requests are in flight, and no requests
+    /// have finished for this many milliseconds.
+    #[serde(default)]
+    pub wait_for_network_idle_ms: Option<u64>,
+    /// Wait until web fonts have loaded.
+    #[serde(default)]
+    pub wait_for_fonts: bool,
+    /// Wait until every `<img>` has loaded or failed to load.
+    #[serde(default)]
+    pub wait_for_images: bool,
+    /// Wait until an element matching this CSS selector is in the page.
+    #[serde(default)]
+    pub wait_for_selector: Option<String>,
+    /// Wait until no element matching this CSS selector is in the page, e.g. a loading spinner.
+    #[serde(default)]
+    pub wait_for_selector_gone: Option<String>,
+    /// Wait until this JavaScript expression is true.
+    #[serde(default)]
+    pub wait_for_script: Option<String>,
+    /// When true, pages are scrolled through from top to bottom one window height at a time
+    /// before capture, waiting for images at each step, so lazy loaded images are shown.
+    /// Defaults to false.
+    #[serde(default)]
+    pub scroll_through: bool,
+    /// Pages taller than this many CSS pixels are captured in tiles of this height and stitched
+    /// together, instead of growing the window to the full page height.
+    /// Not tiled by default.
+    #[serde(default)]
+    pub tile_height: Option<u32>,
+    /// Cookies set for the trusted domain in every browser, e.g. a session cookie.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub trusted_cookies: Vec<Cookie>,
+    /// Cookies set for the testing domain in every browser.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub testing_cookies: Vec<Cookie>,
+    /// Extra HTTP headers sent with every request to the trusted and testing domains, e.g.
+    /// `{"Authorization": "Bearer abc"}`.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Login form to fill in on both domains whenever a browser is launched, before any route
+    /// is captured.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub login: Option<Login>,
+    /// URL glob patterns for requests to answer with an empty response in both pages, e.g. `"*google-analytics.com*"`.
+    /// `*` matches any run of characters and `?` any one character.
+    #[serde(default)]
+    pub block_requests: Vec<String>,
+    /// Rules for requests to send to another URL in both pages. The first matching rule is used.
+    /// Only read from the JSON config file.
+    #[serde(default)]
+    pub rewrite_requests: Vec<RewriteRule>,
+    /// Path to write a JSON summary of the run to, with every route's status, percent difference
+    /// and screenshot paths.
+    #[serde(default)]
+    pub summary_json: Option<String>,
+    /// Format of the report written to `report_path`: `"junit"` for JUnit XML.
+    /// Not reported by default.
+    #[serde(default)]
+    pub reporter: Option<Reporter>,
+    /// Path to write the `reporter` report to. Needed when `reporter` is set.
+    #[serde(default)]
+    pub report_path: Option<String>,
+    /// Folder of approved screenshots to compare the testing pages against, instead of loading
+    /// the trusted pages. Files are named by slug and viewport, e.g. `blog_desktop.png`.
+    /// The trusted domain is then only used to read routes from its sitemap.
+    #[serde(default)]
+    pub baselines: Option<String>,
     /// Test version of root URL of the website.
     pub testing: String,
     /// Trusted/production version of root URL of the website.
     pub trusted: String,
 }
 
+fn default_max_browser_uses() -> usize {
+    50
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+fn default_render_wait_base_ms() -> u64 {
+    30
+}
+
+fn default_render_wait_per_megapixel_ms() -> u64 {
+    100
+}
+
+impl Config {
+    /// The allowed percent difference for `slug`, taking route overrides into account.
+    pub fn threshold_for(&self, slug: &str) -> f64 {
+        self.overrides
+            .get(slug)
+            .and_then(|route| route.threshold)
+            .unwrap_or(self.threshold)
+    }
+
+    /// CSS selectors for elements to leave out of the comparison of `slug`.
+    pub fn ignore_selectors_for(&self, slug: &str) -> Vec<String> {
+        let mut selectors = self.ignore_selectors.clone();
+        if let Some(route) = self.overrides.get(slug) {
+            selectors.extend(route.ignore_selectors.iter().cloned());
+        }
+        selectors
+    }
+
+    /// CSS selectors for elements to capture on their own on `slug`. Empty to capture the whole
+    /// page.
+    pub fn capture_selectors_for(&self, slug: &str) -> Vec<String> {
+        let mut selectors = self.capture_selectors.clone();
+        if let Some(route) = self.overrides.get(slug) {
+            selectors.extend(route.capture_selectors.iter().cloned());
+        }
+        selectors
+    }
+
+    /// Diff settings for `slug`, taking route overrides into account.
+    ///
+    /// Areas found from `ignore_selectors` are only known after capture, and are not included.
+    pub fn diff_options(&self, slug: &str) -> crate::DiffOptions {
+        let mut ignore_rects = self.ignore_rects.clone();
+        if let Some(route) = self.overrides.get(slug) {
+            ignore_rects.extend(route.ignore_rects.iter().cloned());
+        }
+        crate::DiffOptions {
+            threshold: self.threshold_for(slug),
+            metric: self.metric,
+            tolerance: self.tolerance,
+            ignore_antialiasing: self.ignore_antialiasing,
+            align_rows: self.align_rows,
+            trusted_ignore: ignore_rects.clone(),
+            testing_ignore: ignore_rects,
+        }
+    }
+}
+
 fn assert_url(url: &String) -> () {
     if let Err(x) = Url::parse(url) {
         println!("Error parsing domain \"{}\": {:?}", url, x);
@@ -117,6 +626,52 @@ fn assert_threshold(threshold: f64) -> () {
     }
 }
 
+fn assert_viewports(viewports: &[ViewportProfile]) {
+    if viewports.is_empty() {
+        println!("At least one viewport is needed.");
+        std::process::exit(1);
+    }
+    let mut names = HashSet::new();
+    for viewport in viewports {
+        if viewport.name.trim().is_empty() || !names.insert(&viewport.name) {
+            println!("Viewport names should be unique and not empty. Received \"{}\"", viewport.name);
+            std::process::exit(1);
+        }
+        if viewport.width == 0 || viewport.device_scale_factor <= 0.0 {
+            println!("Viewport \"{}\" should have a width and device scale factor above 0.", viewport.name);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn assert_max_browser_uses(uses: usize) {
+    if uses == 0 {
+        println!("Max browser uses should be a whole number above 0. Received {}", uses);
+        std::process::exit(1);
+    }
+}
+
+fn assert_concurrency(concurrency: usize) {
+    if concurrency == 0 {
+        println!("Concurrency should be a whole number above 0. Received {}", concurrency);
+        std::process::exit(1);
+    }
+}
+
+fn assert_tile_height(height: u32) {
+    if height == 0 {
+        println!("Tile height should be a whole number of pixels above 0. Received {}", height);
+        std::process::exit(1);
+    }
+}
+
+fn assert_tolerance(tolerance: f64) {
+    if !(0.0..=1.0).contains(&tolerance) {
+        println!("Tolerance should be between 0 and 1. Received {}", tolerance);
+        std::process::exit(1);
+    }
+}
+
 lazy_static! {
     static ref PROJECT_DIRS: ProjectDirs = ProjectDirs::from("red.allthings", "nitpx", "nitpx").unwrap_or_else(|| {
         println!("Unable to locate path to a project directory. Does the home directory exist? Exiting...\n");
@@ -168,6 +723,7 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
             env::var("NITPX_TRUSTED").unwrap_or_else(|_| {
                 match &file_config {
                     Some(file_config) => file_config.trusted.clone(),
+                    None if cli_config.offline => String::new(),
                     None => {
                         println!("Could not find trusted domain in environment, command line, or config file. Exiting.");
                         std::process::exit(1);
@@ -175,7 +731,9 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
                 }
             })
         });
-    assert_url(&trusted);
+    if !cli_config.offline {
+        assert_url(&trusted);
+    }
 
     let testing = cli_config.testing
         .clone()
@@ -183,6 +741,7 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
             env::var("NITPX_TESTING").unwrap_or_else(|_| {
                 match &file_config {
                     Some(file_config) => file_config.testing.clone(),
+                    None if cli_config.offline => String::new(),
                     None => {
                         println!("Could not find testing domain in environment, command line, or config file. Exiting.");
                         std::process::exit(1);
@@ -190,7 +749,9 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
                 }
             })
         });
-    assert_url(&testing);
+    if !cli_config.offline {
+        assert_url(&testing);
+    }
 
     let screenshots = cli_config.screenshots
         .clone()
@@ -238,6 +799,338 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
 
     assert_threshold(threshold);
 
+    let overrides: HashMap<String, RouteOverrides> = match &file_config {
+        Some(file_config) => file_config.overrides.clone(),
+        None => HashMap::new(),
+    };
+    for route in overrides.values() {
+        if let Some(route_threshold) = route.threshold {
+            assert_threshold(route_threshold);
+        }
+    }
+
+    let metric: DiffMetric = match cli_config.metric.clone().or_else(|| env::var("NITPX_METRIC").ok()) {
+        Some(metric) => metric.parse().unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        }),
+        None => match &file_config {
+            Some(file_config) => file_config.metric,
+            None => DiffMetric::default(),
+        },
+    };
+
+    let tolerance: f64 = cli_config.tolerance
+        .clone()
+        .unwrap_or_else(|| {
+            env::var("NITPX_TOLERANCE").unwrap_or_else(|_| { String::from("") })
+        })
+        .parse::<f64>()
+        .unwrap_or_else(|_| {
+            match &file_config {
+                Some(file_config) => file_config.tolerance,
+                None => 0.0,
+            }
+        });
+
+    assert_tolerance(tolerance);
+
+    let ignore_antialiasing: bool = if cli_config.ignore_antialiasing {
+        true
+    } else {
+        env::var("NITPX_IGNORE_ANTIALIASING")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.ignore_antialiasing,
+                    None => false,
+                }
+            })
+    };
+
+    let align_rows: bool = if cli_config.align_rows {
+        true
+    } else {
+        env::var("NITPX_ALIGN_ROWS")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.align_rows,
+                    None => false,
+                }
+            })
+    };
+
+    let ignore_selectors: Vec<String> = match cli_config.ignore_selectors.clone().or_else(|| env::var("NITPX_IGNORE_SELECTORS").ok()) {
+        Some(selectors) => selectors.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect(),
+        None => match &file_config {
+            Some(file_config) => file_config.ignore_selectors.clone(),
+            None => vec![],
+        },
+    };
+
+    let capture_selectors: Vec<String> = match cli_config.capture_selectors.clone().or_else(|| env::var("NITPX_CAPTURE_SELECTORS").ok()) {
+        Some(selectors) => selectors.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect(),
+        None => match &file_config {
+            Some(file_config) => file_config.capture_selectors.clone(),
+            None => vec![],
+        },
+    };
+
+    let hide_selectors: Vec<String> = match cli_config.hide_selectors.clone().or_else(|| env::var("NITPX_HIDE_SELECTORS").ok()) {
+        Some(selectors) => selectors.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect(),
+        None => match &file_config {
+            Some(file_config) => file_config.hide_selectors.clone(),
+            None => vec![],
+        },
+    };
+
+    let disable_animations: bool = if cli_config.disable_animations {
+        true
+    } else {
+        env::var("NITPX_DISABLE_ANIMATIONS")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.disable_animations,
+                    None => false,
+                }
+            })
+    };
+
+    let stabilize_script: Option<String> = match cli_config.stabilize_script.clone().or_else(|| env::var("NITPX_STABILIZE_SCRIPT").ok()) {
+        Some(path) => Some(path).filter(|x| !x.is_empty()),
+        None => match &file_config {
+            Some(file_config) => file_config.stabilize_script.clone(),
+            None => None,
+        },
+    };
+
+    let freeze_time: Option<i64> = match cli_config.freeze_time.clone().or_else(|| env::var("NITPX_FREEZE_TIME").ok()).filter(|x| !x.is_empty()) {
+        Some(time) => Some(time.parse().unwrap_or_else(|_| {
+            println!("Freeze time should be milliseconds since the Unix epoch. Received {}", time);
+            std::process::exit(1);
+        })),
+        None => file_config.as_ref().and_then(|file_config| file_config.freeze_time),
+    };
+
+    let random_seed: Option<u32> = match cli_config.random_seed.clone().or_else(|| env::var("NITPX_RANDOM_SEED").ok()).filter(|x| !x.is_empty()) {
+        Some(seed) => Some(seed.parse().unwrap_or_else(|_| {
+            println!("Random seed should be a whole number from 0 to {}. Received {}", u32::MAX, seed);
+            std::process::exit(1);
+        })),
+        None => file_config.as_ref().and_then(|file_config| file_config.random_seed),
+    };
+
+    let max_browser_uses: usize = match cli_config.max_browser_uses.clone().or_else(|| env::var("NITPX_MAX_BROWSER_USES").ok()) {
+        Some(uses) => uses.parse().ok().filter(|uses| *uses > 0).unwrap_or_else(|| {
+            println!("Max browser uses should be a whole number above 0. Received {}", uses);
+            std::process::exit(1);
+        }),
+        None => match &file_config {
+            Some(file_config) => file_config.max_browser_uses,
+            None => default_max_browser_uses(),
+        },
+    };
+    assert_max_browser_uses(max_browser_uses);
+
+    let concurrency: usize = match cli_config.concurrency.clone().or_else(|| env::var("NITPX_CONCURRENCY").ok()) {
+        Some(concurrency) => concurrency.parse().ok().filter(|concurrency| *concurrency > 0).unwrap_or_else(|| {
+            println!("Concurrency should be a whole number above 0. Received {}", concurrency);
+            std::process::exit(1);
+        }),
+        None => match &file_config {
+            Some(file_config) => file_config.concurrency,
+            None => default_concurrency(),
+        },
+    };
+    assert_concurrency(concurrency);
+
+    let simultaneous: bool = if cli_config.simultaneous {
+        true
+    } else {
+        env::var("NITPX_SIMULTANEOUS")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.simultaneous,
+                    None => false,
+                }
+            })
+    };
+
+    let viewports: Vec<ViewportProfile> = match &file_config {
+        Some(file_config) => file_config.viewports.clone(),
+        None => default_viewports(),
+    };
+    assert_viewports(&viewports);
+
+    let render_wait_base_ms: u64 = match cli_config.render_wait_base_ms.clone().or_else(|| env::var("NITPX_RENDER_WAIT_BASE_MS").ok()) {
+        Some(ms) => ms.parse().unwrap_or_else(|_| {
+            println!("Render wait base should be a whole number of milliseconds. Received {}", ms);
+            std::process::exit(1);
+        }),
+        None => match &file_config {
+            Some(file_config) => file_config.render_wait_base_ms,
+            None => default_render_wait_base_ms(),
+        },
+    };
+
+    let render_wait_per_megapixel_ms: u64 = match cli_config.render_wait_per_megapixel_ms.clone().or_else(|| env::var("NITPX_RENDER_WAIT_PER_MEGAPIXEL_MS").ok()) {
+        Some(ms) => ms.parse().unwrap_or_else(|_| {
+            println!("Render wait per megapixel should be a whole number of milliseconds. Received {}", ms);
+            std::process::exit(1);
+        }),
+        None => match &file_config {
+            Some(file_config) => file_config.render_wait_per_megapixel_ms,
+            None => default_render_wait_per_megapixel_ms(),
+        },
+    };
+
+    let wait_for_network_idle_ms: Option<u64> = match cli_config.wait_for_network_idle_ms.clone().or_else(|| env::var("NITPX_WAIT_FOR_NETWORK_IDLE_MS").ok()).filter(|x| !x.is_empty()) {
+        Some(ms) => Some(ms.parse().unwrap_or_else(|_| {
+            println!("Network idle wait should be a whole number of milliseconds. Received {}", ms);
+            std::process::exit(1);
+        })),
+        None => file_config.as_ref().and_then(|file_config| file_config.wait_for_network_idle_ms),
+    };
+
+    let wait_for_fonts: bool = if cli_config.wait_for_fonts {
+        true
+    } else {
+        env::var("NITPX_WAIT_FOR_FONTS")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.wait_for_fonts,
+                    None => false,
+                }
+            })
+    };
+
+    let wait_for_images: bool = if cli_config.wait_for_images {
+        true
+    } else {
+        env::var("NITPX_WAIT_FOR_IMAGES")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.wait_for_images,
+                    None => false,
+                }
+            })
+    };
+
+    let wait_for_selector: Option<String> = match cli_config.wait_for_selector.clone().or_else(|| env::var("NITPX_WAIT_FOR_SELECTOR").ok()) {
+        Some(selector) => Some(selector).filter(|x| !x.is_empty()),
+        None => file_config.as_ref().and_then(|file_config| file_config.wait_for_selector.clone()),
+    };
+
+    let wait_for_selector_gone: Option<String> = match cli_config.wait_for_selector_gone.clone().or_else(|| env::var("NITPX_WAIT_FOR_SELECTOR_GONE").ok()) {
+        Some(selector) => Some(selector).filter(|x| !x.is_empty()),
+        None => file_config.as_ref().and_then(|file_config| file_config.wait_for_selector_gone.clone()),
+    };
+
+    let wait_for_script: Option<String> = match cli_config.wait_for_script.clone().or_else(|| env::var("NITPX_WAIT_FOR_SCRIPT").ok()) {
+        Some(script) => Some(script).filter(|x| !x.is_empty()),
+        None => file_config.as_ref().and_then(|file_config| file_config.wait_for_script.clone()),
+    };
+
+    let scroll_through: bool = if cli_config.scroll_through {
+        true
+    } else {
+        env::var("NITPX_SCROLL_THROUGH")
+            .map(|x| x == "true")
+            .unwrap_or_else(|_| {
+                match &file_config {
+                    Some(file_config) => file_config.scroll_through,
+                    None => false,
+                }
+            })
+    };
+
+    let tile_height: Option<u32> = match cli_config.tile_height.clone().or_else(|| env::var("NITPX_TILE_HEIGHT").ok()).filter(|x| !x.is_empty()) {
+        Some(height) => Some(height.parse().ok().filter(|height| *height > 0).unwrap_or_else(|| {
+            println!("Tile height should be a whole number of pixels above 0. Received {}", height);
+            std::process::exit(1);
+        })),
+        None => file_config.as_ref().and_then(|file_config| file_config.tile_height),
+    };
+    if let Some(height) = tile_height {
+        assert_tile_height(height);
+    }
+
+    let trusted_cookies: Vec<Cookie> = match &file_config {
+        Some(file_config) => file_config.trusted_cookies.clone(),
+        None => vec![],
+    };
+
+    let testing_cookies: Vec<Cookie> = match &file_config {
+        Some(file_config) => file_config.testing_cookies.clone(),
+        None => vec![],
+    };
+
+    let headers: HashMap<String, String> = match &file_config {
+        Some(file_config) => file_config.headers.clone(),
+        None => HashMap::new(),
+    };
+
+    let login: Option<Login> = file_config.as_ref().and_then(|file_config| file_config.login.clone());
+
+    let block_requests: Vec<String> = match cli_config.block_requests.clone().or_else(|| env::var("NITPX_BLOCK_REQUESTS").ok()) {
+        Some(patterns) => patterns.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect(),
+        None => match &file_config {
+            Some(file_config) => file_config.block_requests.clone(),
+            None => vec![],
+        },
+    };
+
+    let summary_json: Option<String> = match cli_config.summary_json.clone().or_else(|| env::var("NITPX_SUMMARY_JSON").ok()) {
+        Some(path) => Some(path).filter(|x| !x.is_empty()),
+        None => match &file_config {
+            Some(file_config) => file_config.summary_json.clone(),
+            None => None,
+        },
+    };
+
+    let reporter: Option<Reporter> = match cli_config.reporter.clone().or_else(|| env::var("NITPX_REPORTER").ok()).filter(|x| !x.is_empty()) {
+        Some(reporter) => Some(reporter.parse().unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        })),
+        None => file_config.as_ref().and_then(|file_config| file_config.reporter),
+    };
+
+    let report_path: Option<String> = match cli_config.report_path.clone().or_else(|| env::var("NITPX_REPORT_PATH").ok()) {
+        Some(path) => Some(path).filter(|x| !x.is_empty()),
+        None => match &file_config {
+            Some(file_config) => file_config.report_path.clone(),
+            None => None,
+        },
+    };
+    if let (Some(reporter), None) = (reporter, &report_path) {
+        println!("A report path is needed to write the {} report to.", reporter);
+        std::process::exit(1);
+    }
+
+    let baselines: Option<String> = match cli_config.baselines.clone().or_else(|| env::var("NITPX_BASELINES").ok()) {
+        Some(path) => Some(path).filter(|x| !x.is_empty()),
+        None => match &file_config {
+            Some(file_config) => file_config.baselines.clone(),
+            None => None,
+        },
+    };
+
+    let rewrite_requests: Vec<RewriteRule> = match &file_config {
+        Some(file_config) => file_config.rewrite_requests.clone(),
+        None => vec![],
+    };
+
+    let ignore_rects: Vec<Rect> = match &file_config {
+        Some(file_config) => file_config.ignore_rects.clone(),
+        None => vec![],
+    };
+
     let routes = cli_config.routes
         .clone()
         .unwrap_or_else(|| {
@@ -259,6 +1152,43 @@ pub fn get_config(cli_config: &CliConfig) -> Config {
         screenshots,
         testing,
         threshold,
+        overrides,
+        metric,
+        tolerance,
+        ignore_antialiasing,
+        align_rows,
+        ignore_rects,
+        ignore_selectors,
+        capture_selectors,
+        hide_selectors,
+        disable_animations,
+        stabilize_script,
+        freeze_time,
+        random_seed,
+        max_browser_uses,
+        concurrency,
+        simultaneous,
+        viewports,
+        render_wait_base_ms,
+        render_wait_per_megapixel_ms,
+        wait_for_network_idle_ms,
+        wait_for_fonts,
+        wait_for_images,
+        wait_for_selector,
+        wait_for_selector_gone,
+        wait_for_script,
+        scroll_through,
+        tile_height,
+        trusted_cookies,
+        testing_cookies,
+        headers,
+        login,
+        block_requests,
+        rewrite_requests,
+        summary_json,
+        reporter,
+        report_path,
+        baselines,
         trusted,
     }
 }
@@ -0,0 +1,210 @@
+//! Self-contained HTML report of a run, written next to the screenshots.
+//!
+//! Styles and scripts are inlined and screenshots are linked by file name, so the report works
+//! straight from the filesystem without a network connection.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::report::{xml_escape, RouteReport, Status, Summary};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h2 { border-bottom: 1px solid #ccc; padding-bottom: 0.2em; }
+.route { margin: 0.5em 0; }
+.route > summary { cursor: pointer; padding: 0.3em; }
+.failed > summary, .errored > summary { background: #fde2e2; }
+.skipped > summary { background: #eee; }
+.passed > summary { background: #e2f5e2; }
+.pct { display: inline-block; min-width: 6em; font-weight: bold; }
+.viewport, .message { color: #666; }
+.modes { margin: 0.5em 0; }
+.modes button { padding: 0.3em 0.8em; }
+.viewer > div:not(.modes) { display: none; }
+.viewer[data-mode=side-by-side] .side-by-side { display: flex; gap: 1em; }
+.viewer[data-mode=swipe] .swipe, .viewer[data-mode=onion] .onion { display: block; }
+.viewer[data-mode=side-by-side] button[data-mode=side-by-side],
+.viewer[data-mode=swipe] button[data-mode=swipe],
+.viewer[data-mode=onion] button[data-mode=onion] { font-weight: bold; }
+.side-by-side figure { flex: 1; margin: 0; min-width: 0; }
+.side-by-side img { width: 100%; }
+.frame { overflow: auto; max-height: 80vh; border: 1px solid #ccc; }
+.stack { position: relative; display: inline-block; }
+.stack img { display: block; }
+.stack .overlay { position: absolute; top: 0; left: 0; }
+input[type=range] { width: 100%; }
+";
+
+const SCRIPT: &str = "
+document.querySelectorAll('.viewer').forEach(function (viewer) {
+    viewer.querySelectorAll('.modes button').forEach(function (button) {
+        button.addEventListener('click', function () {
+            viewer.dataset.mode = button.dataset.mode;
+        });
+    });
+    var swipe = viewer.querySelector('.swipe input');
+    swipe.addEventListener('input', function () {
+        viewer.querySelector('.swipe .overlay').style.clipPath = 'inset(0 0 0 ' + swipe.value + '%)';
+    });
+    var onion = viewer.querySelector('.onion input');
+    onion.addEventListener('input', function () {
+        viewer.querySelector('.onion .overlay').style.opacity = onion.value / 100;
+    });
+});
+";
+
+/// Sections of the report, in order.
+const GROUPS: [(Status, &str); 4] = [
+    (Status::Failed, "Failed"),
+    (Status::Errored, "Errored"),
+    (Status::Skipped, "Skipped"),
+    (Status::Passed, "Passed"),
+];
+
+/// Percent-encodes each segment of `path`, so characters slugs may keep, like `?`, `#` and `%`,
+/// aren't read as the query, fragment or an escape.
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| segment.bytes().map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        }).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Screenshot path relative to the report in `dir`. Images from elsewhere, like those diffed
+/// offline, are linked by absolute path.
+fn image_src(path: &str, dir: &str) -> String {
+    let path = Path::new(path);
+    let src = if path.parent() == Some(Path::new(dir)) {
+        path.file_name().map(PathBuf::from).unwrap_or_default()
+    } else {
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    };
+    xml_escape(&encode_path(&src.to_string_lossy()))
+}
+
+/// Trusted, testing and diff images side by side, a swipe slider and an onion skin.
+fn viewer_html(route: &RouteReport, dir: &str) -> String {
+    let trusted = image_src(&route.trusted_path, dir);
+    let testing = image_src(&route.testing_path, dir);
+    let diff = image_src(&route.diff_path, dir);
+    format!("<div class=\"viewer\" data-mode=\"side-by-side\">
+<div class=\"modes\"><button data-mode=\"side-by-side\">Side by side</button> <button data-mode=\"swipe\">Swipe</button> <button data-mode=\"onion\">Onion skin</button></div>
+<div class=\"side-by-side\">
+<figure><figcaption>Trusted</figcaption><img src=\"{trusted}\" loading=\"lazy\"></figure>
+<figure><figcaption>Testing</figcaption><img src=\"{testing}\" loading=\"lazy\"></figure>
+<figure><figcaption>Diff</figcaption><img src=\"{diff}\" loading=\"lazy\"></figure>
+</div>
+<div class=\"swipe\"><label>Trusted on the left, testing on the right <input type=\"range\" min=\"0\" max=\"100\" value=\"50\"></label>
+<div class=\"frame\"><div class=\"stack\"><img src=\"{trusted}\" loading=\"lazy\"><img class=\"overlay\" src=\"{testing}\" loading=\"lazy\" style=\"clip-path: inset(0 0 0 50%)\"></div></div></div>
+<div class=\"onion\"><label>Testing opacity <input type=\"range\" min=\"0\" max=\"100\" value=\"50\"></label>
+<div class=\"frame\"><div class=\"stack\"><img src=\"{trusted}\" loading=\"lazy\"><img class=\"overlay\" src=\"{testing}\" loading=\"lazy\" style=\"opacity: 0.5\"></div></div></div>
+</div>
+", trusted = trusted, testing = testing, diff = diff)
+}
+
+fn route_html(route: &RouteReport, dir: &str) -> String {
+    let status = match route.status {
+        Status::Passed => "passed",
+        Status::Failed => "failed",
+        Status::Skipped => "skipped",
+        Status::Errored => "errored",
+    };
+    let pct = match route.pct_diff {
+        Some(pct_diff) => format!("{}%", pct_diff),
+        None => String::from("&ndash;"),
+    };
+    let mut html = format!(
+        "<details class=\"route {}\">\n<summary><span class=\"pct\">{}</span> {}{}</summary>\n",
+        status,
+        pct,
+        xml_escape(&route.label()),
+        if route.viewport.is_empty() {
+            String::new()
+        } else {
+            format!(" <span class=\"viewport\">at {}</span>", xml_escape(&route.viewport))
+        },
+    );
+    if route.blocked > 0 {
+        html.push_str(&format!("<p class=\"message\">Blocked {} requests</p>\n", route.blocked));
+    }
+    if let Some(message) = &route.message {
+        html.push_str(&format!("<p class=\"message\">{}</p>\n", xml_escape(message)));
+    }
+    if route.status == Status::Failed {
+        html.push_str(&viewer_html(route, dir));
+    }
+    html.push_str("</details>\n");
+    html
+}
+
+/// The report as a single HTML page to be written to `dir`, with routes grouped by status and
+/// sorted by percent difference, largest first.
+pub fn render(summary: &Summary, dir: &str) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>nitpx report</title>\n<style>{}</style>\n</head>\n<body>\n<h1>nitpx report</h1>\n<p>{} passed, {} failed, {} errored, {} skipped</p>\n",
+        STYLE,
+        summary.passed,
+        summary.failed,
+        summary.errored,
+        summary.skipped,
+    );
+    for (status, title) in GROUPS.iter() {
+        let mut routes: Vec<&RouteReport> = summary.routes.iter().filter(|route| route.status == *status).collect();
+        if routes.is_empty() {
+            continue;
+        }
+        routes.sort_by(|a, b| b.pct_diff.partial_cmp(&a.pct_diff).unwrap_or(Ordering::Equal));
+        html.push_str(&format!("<h2>{} ({})</h2>\n", title, routes.len()));
+        for route in routes {
+            html.push_str(&route_html(route, dir));
+        }
+    }
+    html.push_str(&format!("<script>{}</script>\n</body>\n</html>\n", SCRIPT));
+    html
+}
+
+/// Writes the report to `index.html` in `dir`, and returns its path.
+pub fn write(summary: &Summary, dir: &str) -> io::Result<String> {
+    let path = format!("{}/index.html", dir);
+    fs::write(&path, render(summary, dir))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(route: &str, status: Status, pct_diff: Option<f64>) -> RouteReport {
+        let mut report = RouteReport::new(route, None, "desktop", "/shots", &format!("{}_desktop", route), status, 0.0);
+        report.pct_diff = pct_diff;
+        report
+    }
+
+    #[test]
+    fn failed_routes_come_first_largest_difference_first() {
+        let html = render(&Summary::new(vec![
+            report("about", Status::Passed, Some(0.0)),
+            report("blog", Status::Failed, Some(1.5)),
+            report("shop", Status::Failed, Some(12.0)),
+            report("huge", Status::Skipped, None),
+        ]), "/shots");
+
+        let position = |text: &str| html.find(text).unwrap();
+        assert!(position("Failed (2)") < position("Skipped (1)"));
+        assert!(position("Skipped (1)") < position("Passed (1)"));
+        assert!(position("12%") < position("1.5%"));
+        assert!(html.contains("<img src=\"shop_desktop_diff.png\""));
+        assert!(!html.contains("about_desktop_diff.png"));
+    }
+
+    #[test]
+    fn image_sources_are_percent_encoded() {
+        assert_eq!(image_src("/shots/search?q=x#top_desktop_diff.png", "/shots"), "search%3Fq%3Dx%23top_desktop_diff.png");
+        assert_eq!(image_src("/shots/100%_desktop_testing.png", "/shots"), "100%25_desktop_testing.png");
+    }
+}
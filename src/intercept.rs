@@ -0,0 +1,412 @@
+//! Blocking and rewriting the requests pages make while they are captured, and adding the
+//! configured headers to requests for the trusted and testing sites.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use headless_chrome::browser::tab::{RequestInterceptionDecision, RequestInterceptor};
+use headless_chrome::protocol::network::methods::{Enable, GetCookies, RequestPattern};
+use headless_chrome::protocol::network::{Cookie, Request};
+use headless_chrome::Tab;
+use url::Url;
+
+use crate::config::{Config, RewriteRule};
+
+/// What to do with a request.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    Block,
+    /// Request this URL instead.
+    Rewrite(String),
+}
+
+/// Matches `text` against a glob `pattern`, where `*` is any run of characters and `?` is any
+/// one character. Returns what each `*` matched, or `None` if `text` doesn't match.
+pub fn glob_captures<'a>(pattern: &str, text: &'a str) -> Option<Vec<&'a str>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut captures = vec![];
+    if match_from(&pattern, text, 0, &mut captures) {
+        Some(captures)
+    } else {
+        None
+    }
+}
+
+fn match_from<'a>(pattern: &[char], text: &'a str, pos: usize, captures: &mut Vec<&'a str>) -> bool {
+    match pattern.split_first() {
+        None => pos == text.len(),
+        Some((&'*', rest)) => {
+            // Shortest match first, growing one character at a time.
+            let mut end = pos;
+            loop {
+                captures.push(&text[pos..end]);
+                if match_from(rest, text, end, captures) {
+                    return true;
+                }
+                captures.pop();
+                match text[end..].chars().next() {
+                    Some(c) => end += c.len_utf8(),
+                    None => return false,
+                }
+            }
+        }
+        Some((&'?', rest)) => match text[pos..].chars().next() {
+            Some(c) => match_from(rest, text, pos + c.len_utf8(), captures),
+            None => false,
+        },
+        Some((&c, rest)) => text[pos..].starts_with(c) && match_from(rest, text, pos + c.len_utf8(), captures),
+    }
+}
+
+/// `rule.to` with each `*` replaced by what the matching `*` in `rule.from` matched in `url`.
+fn rewrite(rule: &RewriteRule, url: &str) -> Option<String> {
+    let captures = glob_captures(&rule.from, url)?;
+    let mut captures = captures.into_iter();
+    Some(rule.to.split('*').enumerate().fold(String::new(), |mut rewritten, (i, part)| {
+        if i > 0 {
+            rewritten.push_str(captures.next().unwrap_or(""));
+        }
+        rewritten.push_str(part);
+        rewritten
+    }))
+}
+
+/// Blocking wins over rewriting, and the first matching rewrite rule is used.
+pub fn action_for(block: &[String], rewrites: &[RewriteRule], url: &str) -> Action {
+    if block.iter().any(|pattern| glob_captures(pattern, url).is_some()) {
+        return Action::Block;
+    }
+    rewrites.iter()
+        .find_map(|rule| rewrite(rule, url))
+        .map(Action::Rewrite)
+        .unwrap_or(Action::Continue)
+}
+
+/// Whether `url` has the same origin as one of `sites`.
+fn on_sites(sites: &[Url], url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(url) => sites.iter().any(|site| site.origin() == url.origin()),
+        Err(_) => false,
+    }
+}
+
+/// The request's own headers with `extra` added, replacing any of the same name.
+fn with_headers<'a>(headers: &'a HashMap<String, String>, extra: &'a HashMap<String, String>) -> HashMap<&'a str, &'a str> {
+    let mut merged: HashMap<&str, &str> = headers.iter()
+        .filter(|(name, _)| !extra.keys().any(|extra_name| extra_name.eq_ignore_ascii_case(name)))
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    merged.extend(extra.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+    merged
+}
+
+/// Answer to a blocked request: an empty response, so nothing from it shows up in the page.
+fn blocked_response() -> String {
+    base64::encode("HTTP/1.1 204 No Content\r\n\r\n")
+}
+
+/// Answer to a rewritten request: a redirect to `url`. Requests made with CORS from `origin`
+/// are allowed to follow it.
+fn redirect_response(url: &str, origin: Option<&str>) -> String {
+    let mut response = format!("HTTP/1.1 307 Temporary Redirect\r\nLocation: {}\r\n", url);
+    if let Some(origin) = origin {
+        response.push_str(&format!(
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Credentials: true\r\n",
+            origin,
+        ));
+    }
+    response.push_str("\r\n");
+    base64::encode(&response)
+}
+
+/// Answer to a request nitpx sent itself: the response it got, with the body decoded from any
+/// chunks and its length given up front.
+fn fetched_response(status: u16, status_text: &str, headers: &[(String, String)], body: &[u8]) -> String {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, status_text);
+    for (name, value) in headers {
+        if !name.eq_ignore_ascii_case("transfer-encoding") && !name.eq_ignore_ascii_case("content-length") {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    response.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    base64::encode(&[response.as_bytes(), body].concat())
+}
+
+/// How long a request nitpx sends itself may take to connect, and then to respond.
+const FETCH_TIMEOUT_MS: u64 = 30_000;
+
+/// Sends `request` with `headers` in place of its own, without following redirects, and returns
+/// the response for the page.
+fn fetch(request: &Request, headers: &HashMap<&str, &str>) -> Result<String, Box<dyn Error>> {
+    let mut fetch = ureq::request(&request.method, &request.url);
+    fetch.redirects(0).timeout_connect(FETCH_TIMEOUT_MS).timeout_read(FETCH_TIMEOUT_MS);
+    for (name, value) in headers {
+        fetch.set(name, value);
+    }
+    let response = match &request.post_data {
+        Some(post_data) => fetch.send_string(post_data),
+        None => fetch.call(),
+    };
+    if let Some(e) = response.synthetic_error() {
+        return Err(e.to_string().into());
+    }
+
+    let mut names: Vec<String> = vec![];
+    for name in response.headers_names() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let headers: Vec<(String, String)> = names.iter()
+        .flat_map(|name| response.all(name).into_iter().map(move |value| (name.clone(), value.to_string())))
+        .collect();
+    let (status, status_text) = (response.status(), response.status_text().to_string());
+    let mut body = vec![];
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(fetched_response(status, &status_text, &headers, &body))
+}
+
+/// The `Cookie` header the browser would send with `cookies`.
+fn cookie_header(cookies: &[Cookie]) -> Option<String> {
+    if cookies.is_empty() {
+        return None;
+    }
+    Some(cookies.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect::<Vec<_>>().join("; "))
+}
+
+/// Requests of a tab that were handled differently than the page asked for.
+#[derive(Debug, Default)]
+pub struct RequestCounts {
+    /// Requests answered with an empty response instead of being sent.
+    pub blocked: AtomicUsize,
+    /// Requests for the trusted or testing sites that couldn't be sent with the configured
+    /// `headers`, and were sent without them.
+    pub without_headers: AtomicUsize,
+}
+
+/// What the requests of a tab are checked against.
+struct Rules {
+    block: Vec<String>,
+    rewrites: Vec<RewriteRule>,
+    headers: HashMap<String, String>,
+    sites: Vec<Url>,
+}
+
+/// Decides how to answer `request`. headless_chrome continues the request once with the
+/// decision, and that is the only answer it gets.
+///
+/// Chrome can't add headers to a request it continues, so requests that need the configured
+/// `headers` are sent by `fetch` with them and the browser's `cookies` for the request, and
+/// answered with the response. When that fails, they are continued without the headers.
+fn decide<C, F>(rules: &Rules, counts: &RequestCounts, request: &Request, cookies: C, fetch: F) -> RequestInterceptionDecision
+where
+    C: FnOnce() -> Option<String>,
+    F: FnOnce(&Request, &HashMap<&str, &str>) -> Result<String, Box<dyn Error>>,
+{
+    match action_for(&rules.block, &rules.rewrites, &request.url) {
+        Action::Block => {
+            counts.blocked.fetch_add(1, Ordering::SeqCst);
+            return RequestInterceptionDecision::Response(blocked_response());
+        }
+        Action::Rewrite(url) => {
+            let origin = request.headers.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("origin"))
+                .map(|(_, origin)| origin.as_str());
+            return RequestInterceptionDecision::Response(redirect_response(&url, origin));
+        }
+        Action::Continue => {}
+    }
+    if rules.headers.is_empty() || !on_sites(&rules.sites, &request.url) {
+        return RequestInterceptionDecision::Continue;
+    }
+    let mut extra = rules.headers.clone();
+    if let Some(cookies) = cookies() {
+        extra.insert(String::from("Cookie"), cookies);
+    }
+    match fetch(request, &with_headers(&request.headers, &extra)) {
+        Ok(response) => RequestInterceptionDecision::Response(response),
+        Err(_) => {
+            counts.without_headers.fetch_add(1, Ordering::SeqCst);
+            RequestInterceptionDecision::Continue
+        }
+    }
+}
+
+/// Starts blocking and rewriting the requests of `tab` as configured, for every page it loads,
+/// and adds the configured `headers` to requests for the trusted and testing sites. Other sites
+/// never see the headers, since they often carry credentials.
+///
+/// Blocked requests get an empty response, and rewritten requests a redirect to their new URL.
+/// Requests that need headers are sent by nitpx itself, and get the response it got.
+pub fn intercept_requests(tab: &Tab, config: &Config) -> Result<Arc<RequestCounts>, Box<dyn Error>> {
+    let counts = Arc::new(RequestCounts::default());
+    if config.block_requests.is_empty() && config.rewrite_requests.is_empty() && config.headers.is_empty() {
+        return Ok(counts);
+    }
+
+    let rules = Rules {
+        block: config.block_requests.clone(),
+        rewrites: config.rewrite_requests.clone(),
+        headers: config.headers.clone(),
+        sites: [&config.trusted, &config.testing].iter()
+            .filter_map(|site| Url::parse(site).ok())
+            .collect(),
+    };
+    let tab_counts = Arc::clone(&counts);
+    let interceptor: RequestInterceptor = Box::new(move |transport, session_id, intercepted| {
+        let request = &intercepted.request;
+        let cookies = || {
+            let found = transport.call_method_on_target(session_id, GetCookies { urls: Some(vec![request.url.clone()]) });
+            found.ok().and_then(|found| cookie_header(&found.cookies))
+        };
+        decide(&rules, &tab_counts, request, cookies, fetch)
+    });
+
+    tab.call_method(Enable {})?;
+    tab.enable_request_interception(
+        &[RequestPattern { url_pattern: Some("*"), resource_type: None, interception_stage: None }],
+        interceptor,
+    )?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn globs_match_whole_urls() {
+        assert_eq!(
+            glob_captures("https://*.example.com/*.js", "https://cdn.example.com/app/main.js"),
+            Some(vec!["cdn", "app/main"]),
+        );
+        assert_eq!(glob_captures("*google-analytics.com*", "https://www.google-analytics.com/analytics.js").map(|c| c.len()), Some(2));
+        assert_eq!(glob_captures("https://example.com/?", "https://example.com/ab"), None);
+        assert_eq!(glob_captures("https://example.com/", "https://example.com/app"), None);
+    }
+
+    #[test]
+    fn blocking_wins_over_rewriting() {
+        let block = vec![String::from("*/ads/*")];
+        let rewrites = vec![RewriteRule {
+            from: String::from("https://testing.example.com/static/*"),
+            to: String::from("https://cdn.example.com/*"),
+        }];
+
+        assert_eq!(
+            action_for(&block, &rewrites, "https://testing.example.com/static/app.css"),
+            Action::Rewrite(String::from("https://cdn.example.com/app.css")),
+        );
+        assert_eq!(action_for(&block, &rewrites, "https://testing.example.com/static/ads/banner.png"), Action::Block);
+        assert_eq!(action_for(&block, &rewrites, "https://testing.example.com/about"), Action::Continue);
+    }
+
+    #[test]
+    fn headers_only_go_to_the_trusted_and_testing_sites() {
+        let sites = vec![
+            Url::parse("https://trusted.example.com/").unwrap(),
+            Url::parse("http://localhost:8081/app/").unwrap(),
+        ];
+        assert!(on_sites(&sites, "https://trusted.example.com/static/app.js"));
+        assert!(on_sites(&sites, "http://localhost:8081/api/items"));
+        assert!(!on_sites(&sites, "https://trusted.example.com.evil.net/"));
+        assert!(!on_sites(&sites, "https://www.google-analytics.com/analytics.js"));
+
+        let mut request = HashMap::new();
+        request.insert(String::from("authorization"), String::from("old"));
+        request.insert(String::from("Accept"), String::from("*/*"));
+        let mut extra = HashMap::new();
+        extra.insert(String::from("Authorization"), String::from("Bearer token"));
+        let merged = with_headers(&request, &extra);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged.get("Authorization"), Some(&"Bearer token"));
+        assert_eq!(merged.get("Accept"), Some(&"*/*"));
+    }
+
+    #[test]
+    fn rewrites_redirect_and_allow_the_requesting_origin() {
+        let decode = |response: String| String::from_utf8(base64::decode(&response).unwrap()).unwrap();
+        assert_eq!(decode(blocked_response()), "HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(
+            decode(redirect_response("https://cdn.example.com/app.css", None)),
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: https://cdn.example.com/app.css\r\n\r\n",
+        );
+        assert!(decode(redirect_response("https://cdn.example.com/api", Some("https://testing.example.com")))
+            .contains("\r\nAccess-Control-Allow-Origin: https://testing.example.com\r\n"));
+    }
+
+    fn request(url: &str) -> Request {
+        Request {
+            url: url.to_string(),
+            url_fragment: None,
+            method: String::from("GET"),
+            headers: HashMap::new(),
+            post_data: None,
+            has_post_data: None,
+            mixed_content_type: None,
+            initial_priority: String::from("High"),
+            referrer_policy: String::from("no-referrer-when-downgrade"),
+            is_link_preload: None,
+        }
+    }
+
+    #[test]
+    fn header_requests_are_answered_once_with_the_fetched_response() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("Authorization"), String::from("Bearer token"));
+        let rules = Rules {
+            block: vec![],
+            rewrites: vec![],
+            headers,
+            sites: vec![Url::parse("https://testing.example.com/").unwrap()],
+        };
+        let counts = RequestCounts::default();
+
+        // headless_chrome continues the request once with the decision, so the fetch must be the
+        // only other thing that sends it.
+        let fetches = Cell::new(0);
+        let fetch = |_: &Request, headers: &HashMap<&str, &str>| -> Result<String, Box<dyn Error>> {
+            fetches.set(fetches.get() + 1);
+            assert_eq!(headers.get("Authorization"), Some(&"Bearer token"));
+            assert_eq!(headers.get("Cookie"), Some(&"session=abc"));
+            Ok(String::from("fetched"))
+        };
+        let cookies = || Some(String::from("session=abc"));
+        match decide(&rules, &counts, &request("https://testing.example.com/api"), cookies, fetch) {
+            RequestInterceptionDecision::Response(response) => assert_eq!(response, "fetched"),
+            decision => panic!("expected the fetched response, got {:?}", decision),
+        }
+        assert_eq!(fetches.get(), 1);
+
+        let no_cookies = || -> Option<String> { panic!("other sites don't need cookies") };
+        let no_fetch = |_: &Request, _: &HashMap<&str, &str>| -> Result<String, Box<dyn Error>> { panic!("other sites aren't fetched") };
+        let decision = decide(&rules, &counts, &request("https://cdn.example.com/app.js"), no_cookies, no_fetch);
+        assert!(matches!(decision, RequestInterceptionDecision::Continue));
+
+        let failed = |_: &Request, _: &HashMap<&str, &str>| -> Result<String, Box<dyn Error>> { Err("refused".into()) };
+        let decision = decide(&rules, &counts, &request("https://testing.example.com/api"), || None, failed);
+        assert!(matches!(decision, RequestInterceptionDecision::Continue));
+        assert_eq!(counts.without_headers.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn fetched_responses_are_passed_on_whole() {
+        let headers = vec![
+            (String::from("content-type"), String::from("text/plain")),
+            (String::from("transfer-encoding"), String::from("chunked")),
+            (String::from("set-cookie"), String::from("a=1")),
+            (String::from("set-cookie"), String::from("b=2")),
+        ];
+        let response = base64::decode(&fetched_response(200, "OK", &headers, b"hello")).unwrap();
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\nset-cookie: a=1\r\nset-cookie: b=2\r\nContent-Length: 5\r\n\r\nhello",
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
-use std::fs;
+use std::fs::{self, File};
 use std::fmt;
+use std::io;
 extern crate image;
 extern crate ureq;
 extern crate md5;
@@ -13,33 +14,119 @@ extern crate serde_xml_rs;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod align;
+pub mod baseline;
 pub mod browser;
+mod cdp;
+mod intercept;
+pub mod color;
+pub mod html_report;
+pub mod offline;
+mod readiness;
+pub mod regions;
+pub mod report;
+pub mod route_log;
+mod tiles;
 pub mod url_utils;
 pub mod config;
 
-// TODO: Better error for diffs
+use align::RowPair;
+pub use color::DiffMetric;
+pub use regions::{Rect, Region};
+
+/// Outcome of comparing a trusted screenshot against a testing screenshot.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparisonResult {
+    /// Percent difference between the screenshots, from 0 to 100.
+    pub pct_diff: f64,
+    /// Percent difference that was allowed.
+    pub threshold: f64,
+    /// Number of pixels counted as changed, including area only present in one screenshot.
+    pub changed_px: u64,
+    /// Number of differing pixels ignored as anti-aliasing.
+    pub antialiased_px: u64,
+    /// Number of pixels inside ignored areas, which are left out of `pct_diff`.
+    pub ignored_px: u64,
+    /// Width and height of the trusted screenshot.
+    pub trusted_dimensions: (u32, u32),
+    /// Width and height of the testing screenshot.
+    pub testing_dimensions: (u32, u32),
+    /// Groups of changed pixels, in diff image coordinates.
+    pub regions: Vec<Region>,
+    pub trusted_path: String,
+    pub testing_path: String,
+    pub diff_path: String,
+}
+
+/// Why a comparison failed.
 #[derive(Debug)]
-struct DiffError {
-    pct_diff: f64
+pub enum CompareError {
+    /// A screenshot or the diff image could not be read or written.
+    Io(io::Error),
+    /// A screenshot could not be decoded as an image.
+    Decode(image::ImageError),
+    /// The screenshots have different sizes.
+    DimensionMismatch(Box<ComparisonResult>),
+    /// The screenshots differ by more than the allowed threshold.
+    ThresholdExceeded(Box<ComparisonResult>),
 }
 
-impl DiffError {
-    fn new(pct_diff: f64) -> DiffError {
-        DiffError{pct_diff: pct_diff}
+impl CompareError {
+    /// The comparison details, when the screenshots could be diffed.
+    pub fn result(&self) -> Option<&ComparisonResult> {
+        match self {
+            CompareError::DimensionMismatch(result) | CompareError::ThresholdExceeded(result) => Some(result),
+            CompareError::Io(_) | CompareError::Decode(_) => None,
+        }
     }
 }
 
-impl fmt::Display for DiffError {
+impl fmt::Display for CompareError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,"diff showed a {}%", self.pct_diff)
+        match self {
+            CompareError::Io(e) => write!(f, "image I/O failed: {}", e),
+            CompareError::Decode(e) => write!(f, "could not decode image: {}", e),
+            CompareError::DimensionMismatch(result) => write!(
+                f,
+                "trusted screenshot is {}x{} but testing screenshot is {}x{}, diff showed a {}% difference",
+                result.trusted_dimensions.0,
+                result.trusted_dimensions.1,
+                result.testing_dimensions.0,
+                result.testing_dimensions.1,
+                result.pct_diff,
+            ),
+            CompareError::ThresholdExceeded(result) => write!(
+                f,
+                "diff showed a {}% difference, {}% allowed",
+                result.pct_diff,
+                result.threshold,
+            ),
+        }
     }
 }
 
-impl Error for DiffError {
-    fn description(&self) -> &str {
-        "still no num in this str"
-        // format!("{}", %self.pct_diff)
-        // String::from(self.pct_diff.to_string().clone())
+impl Error for CompareError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompareError::Io(e) => Some(e),
+            CompareError::Decode(e) => Some(e),
+            CompareError::DimensionMismatch(_) | CompareError::ThresholdExceeded(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CompareError {
+    fn from(e: io::Error) -> CompareError {
+        CompareError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for CompareError {
+    fn from(e: image::ImageError) -> CompareError {
+        match e {
+            image::ImageError::IoError(e) => CompareError::Io(e),
+            e => CompareError::Decode(e),
+        }
     }
 }
 
@@ -70,6 +157,7 @@ impl Error for SkipError {
 
 use image::{
     GenericImageView,
+    ImageDecoder,
     Rgba,
     RgbaImage,
     DynamicImage,
@@ -77,59 +165,454 @@ use image::{
     Pixel,
 };
 
-fn diff_rgba3(rgba1 : Rgba<u8>, rgba2 : Rgba<u8>) -> i32 {
-    (rgba1[0] as i32 - rgba2[0] as i32).abs()
-        + (rgba1[1] as i32 - rgba2[1] as i32).abs()
-        + (rgba1[2] as i32 - rgba2[2] as i32).abs()
+/// Settings for how two screenshots are compared.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Percent difference (0 to 100) allowed before the comparison fails.
+    pub threshold: f64,
+    /// How the difference between two pixels is measured.
+    pub metric: DiffMetric,
+    /// Per pixel difference (0 to 1) under `metric` at or below which a pixel counts as unchanged.
+    pub tolerance: f64,
+    /// When true, pixels that look like anti-aliasing differences are not counted as changed.
+    pub ignore_antialiasing: bool,
+    /// When true, rows are aligned like lines in a text diff before comparing, so content that
+    /// only moved up or down counts as unchanged.
+    pub align_rows: bool,
+    /// Areas of the trusted screenshot left out of the comparison.
+    pub trusted_ignore: Vec<Rect>,
+    /// Areas of the testing screenshot left out of the comparison.
+    pub testing_ignore: Vec<Rect>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            threshold: 0.0,
+            metric: DiffMetric::Sum,
+            tolerance: 0.0,
+            ignore_antialiasing: false,
+            align_rows: false,
+            trusted_ignore: vec![],
+            testing_ignore: vec![],
+        }
+    }
+}
+
+struct PixelDiff {
+    pct_diff: f64,
+    changed_px: u64,
+    antialiased_px: u64,
+    ignored_px: u64,
+    regions: Vec<Region>,
+    diff_img: RgbaImage,
+}
+
+/// Neighbours of `(x, y)` within the image bounds, excluding `(x, y)` itself.
+/// The second value is true when `(x, y)` sits on the image edge.
+fn neighbours(x: u32, y: u32, width: u32, height: u32) -> (Vec<(u32, u32)>, bool) {
+    let x0 = x.saturating_sub(1);
+    let y0 = y.saturating_sub(1);
+    let x2 = (x + 1).min(width - 1);
+    let y2 = (y + 1).min(height - 1);
+    let on_edge = x == x0 || x == x2 || y == y0 || y == y2;
+
+    let mut points = vec![];
+    for nx in x0..=x2 {
+        for ny in y0..=y2 {
+            if nx != x || ny != y {
+                points.push((nx, ny));
+            }
+        }
+    }
+    (points, on_edge)
+}
+
+/// True when more than two neighbours of `(x, y)` have exactly its color.
+fn has_many_siblings(img: &DynamicImage, x: u32, y: u32) -> bool {
+    let (width, height) = img.dimensions();
+    if x >= width || y >= height {
+        return false;
+    }
+    let px = img.get_pixel(x, y);
+    let (points, on_edge) = neighbours(x, y, width, height);
+    let mut zeroes = if on_edge { 1 } else { 0 };
+    for (nx, ny) in points {
+        if img.get_pixel(nx, ny) == px {
+            zeroes += 1;
+            if zeroes > 2 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Whether the pixel at `(x, y)` in `img` looks like anti-aliasing, following pixelmatch's
+/// approach: the pixel sits between a darkest and a brightest neighbour, and one of those
+/// neighbours is part of a flat area in both images.
+///
+/// Row `y` of `img` lines up with row `other_y` of `other`.
+fn antialiased(img: &DynamicImage, x: u32, y: u32, other: &DynamicImage, other_y: u32) -> bool {
+    let (width, height) = img.dimensions();
+    let center = color::brightness(img.get_pixel(x, y));
+    let (points, on_edge) = neighbours(x, y, width, height);
+    let mut zeroes = if on_edge { 1 } else { 0 };
+    let mut min = 0.0;
+    let mut max = 0.0;
+    let mut darkest = None;
+    let mut brightest = None;
+
+    for (nx, ny) in points {
+        let delta = center - color::brightness(img.get_pixel(nx, ny));
+        if delta == 0.0 {
+            zeroes += 1;
+            if zeroes > 2 {
+                return false;
+            }
+        } else if delta < min {
+            min = delta;
+            darkest = Some((nx, ny));
+        } else if delta > max {
+            max = delta;
+            brightest = Some((nx, ny));
+        }
+    }
+
+    let other_has_many_siblings = |nx: u32, ny: u32| {
+        (ny + other_y).checked_sub(y).is_some_and(|oy| has_many_siblings(other, nx, oy))
+    };
+    match (darkest, brightest) {
+        (Some((min_x, min_y)), Some((max_x, max_y))) => {
+            (has_many_siblings(img, min_x, min_y) && other_has_many_siblings(min_x, min_y))
+                || (has_many_siblings(img, max_x, max_y) && other_has_many_siblings(max_x, max_y))
+        }
+        _ => false,
+    }
 }
 
-fn get_pct_diff(img_trusted: &DynamicImage, img_testing: &DynamicImage) -> (f64, RgbaImage) {
+fn get_pct_diff(
+    img_trusted: &DynamicImage,
+    img_testing: &DynamicImage,
+    options: &DiffOptions,
+) -> PixelDiff {
     let highlight_rgba = Rgba([255,165,0,188]);
-    let mut accum = 0;
-    let zipper = img_trusted.pixels().zip(img_testing.pixels());
+    let antialiased_rgba = Rgba([255,255,0,188]);
+    // Area that only exists in one of the screenshots, when their sizes differ
+    // or when rows were removed or inserted.
+    let trusted_only_rgba = Rgba([255,0,255,188]);
+    let testing_only_rgba = Rgba([0,191,255,188]);
+    let ignored_rgba = Rgba([128,128,128,160]);
+    let mut accum = 0.0;
+    let mut antialiased_px = 0;
+    let mut ignored_px = 0;
+    // Canvas corners that neither screenshot covers, left out of the percentage.
+    let mut padding_px = 0;
+    let trusted_ignored = |x: u32, y: u32| options.trusted_ignore.iter().any(|rect| rect.contains(x, y));
+    let testing_ignored = |x: u32, y: u32| options.testing_ignore.iter().any(|rect| rect.contains(x, y));
 
-    let (width, height) = img_trusted.dimensions();
+    // Both images are padded to a common canvas so rows line up even when the sizes differ.
+    let (trusted_width, trusted_height) = img_trusted.dimensions();
+    let (testing_width, testing_height) = img_testing.dimensions();
+    let rows = if options.align_rows {
+        align::align_rows(&align::hash_rows(img_trusted), &align::hash_rows(img_testing))
+    } else {
+        align::positional_rows(trusted_height, testing_height)
+    };
+    let width = trusted_width.max(testing_width);
+    let height = rows.len() as u32;
     let mut diff_img: RgbaImage = ImageBuffer::new(width, height);
+    let mut changed_mask = vec![false; (width * height) as usize];
 
-    for (px_trusted, px_testing) in zipper {
-        let amount_different = diff_rgba3(px_trusted.2, px_testing.2);
-        let diffed_px = if amount_different > 0 {
-            let mut px_to_mark = px_testing.2.clone();
-            px_to_mark.blend(&highlight_rgba);
-            px_to_mark
-        } else {
-            px_trusted.2
+    for (y, row) in rows.iter().enumerate() {
+        let (trusted_y, testing_y) = match *row {
+            RowPair::Both(trusted_y, testing_y) => (Some(trusted_y), Some(testing_y)),
+            RowPair::TrustedOnly(trusted_y) => (Some(trusted_y), None),
+            RowPair::TestingOnly(testing_y) => (None, Some(testing_y)),
         };
-        diff_img.put_pixel(px_trusted.0, px_trusted.1, diffed_px);
-        accum += amount_different;
+        for x in 0..width {
+            let mask_index = y * width as usize + x as usize;
+            let in_trusted = trusted_y.filter(|_| x < trusted_width);
+            let in_testing = testing_y.filter(|_| x < testing_width);
+            let ignored = in_trusted.is_some_and(|trusted_y| trusted_ignored(x, trusted_y))
+                || in_testing.is_some_and(|testing_y| testing_ignored(x, testing_y));
+            let diffed_px = match (in_trusted, in_testing) {
+                _ if ignored => {
+                    ignored_px += 1;
+                    let mut px_to_mark = in_testing
+                        .map(|testing_y| img_testing.get_pixel(x, testing_y))
+                        .or_else(|| in_trusted.map(|trusted_y| img_trusted.get_pixel(x, trusted_y)))
+                        .unwrap_or(Rgba([0,0,0,0]));
+                    // Diagonal stripes, so ignored areas read as hatched.
+                    if (x as usize + y) & 4 == 0 {
+                        px_to_mark.blend(&ignored_rgba);
+                    }
+                    px_to_mark
+                }
+                (Some(trusted_y), Some(testing_y)) => {
+                    let px_trusted = img_trusted.get_pixel(x, trusted_y);
+                    let px_testing = img_testing.get_pixel(x, testing_y);
+                    let delta = color::pixel_delta(options.metric, px_trusted, px_testing);
+                    if delta <= options.tolerance {
+                        px_trusted
+                    } else if options.ignore_antialiasing && (
+                        antialiased(img_trusted, x, trusted_y, img_testing, testing_y)
+                            || antialiased(img_testing, x, testing_y, img_trusted, trusted_y)
+                    ) {
+                        antialiased_px += 1;
+                        let mut px_to_mark = px_testing;
+                        px_to_mark.blend(&antialiased_rgba);
+                        px_to_mark
+                    } else {
+                        accum += delta;
+                        changed_mask[mask_index] = true;
+                        let mut px_to_mark = px_testing;
+                        px_to_mark.blend(&highlight_rgba);
+                        px_to_mark
+                    }
+                }
+                (Some(trusted_y), None) => {
+                    accum += 1.0;
+                    changed_mask[mask_index] = true;
+                    let mut px_to_mark = img_trusted.get_pixel(x, trusted_y);
+                    px_to_mark.blend(&trusted_only_rgba);
+                    px_to_mark
+                }
+                (None, Some(testing_y)) => {
+                    accum += 1.0;
+                    changed_mask[mask_index] = true;
+                    let mut px_to_mark = img_testing.get_pixel(x, testing_y);
+                    px_to_mark.blend(&testing_only_rgba);
+                    px_to_mark
+                }
+                (None, None) => {
+                    padding_px += 1;
+                    Rgba([0,0,0,0])
+                }
+            };
+            diff_img.put_pixel(x, y as u32, diffed_px);
+        }
     }
-    let pct_diff = accum as f64 * 100.0/ (255.0 * 3.0 * (width * height) as f64);
-    println!("Percent difference {}", pct_diff);
+    let compared_px = (width * height) as u64 - padding_px - ignored_px;
+    let pct_diff = if compared_px > 0 { accum * 100.0 / compared_px as f64 } else { 0.0 };
+    let changed_px = changed_mask.iter().filter(|changed| **changed).count() as u64;
+    let regions = regions::find_regions(&changed_mask, width, height);
+    regions::draw_outlines(&mut diff_img, &regions);
 
-    (pct_diff, diff_img)
+    PixelDiff {
+        pct_diff,
+        changed_px,
+        antialiased_px,
+        ignored_px,
+        regions,
+        diff_img,
+    }
+}
+
+/// Reads the width and height from a PNG header without decoding the whole image.
+fn png_dimensions(path: &str) -> Result<(u32, u32), CompareError> {
+    Ok(image::png::PNGDecoder::new(File::open(path)?).dimensions()?)
 }
 
+/// Diffs the trusted and testing screenshots, writing the highlighted diff to `diff_path`.
+/// Changed regions are outlined in red in the diff.
+///
+/// The comparison fails when the screenshots differ in size, or when the percent difference
+/// exceeds `options.threshold`.
 pub fn compare(
     trusted_path: String,
     testing_path: String,
     diff_path: String,
     images_identical: bool,
-) -> Result<(), Box<dyn Error>> {
+    options: &DiffOptions,
+) -> Result<ComparisonResult, CompareError> {
     if images_identical {
         fs::copy(&trusted_path, &diff_path)?;
-        Ok(())
+        let dimensions = png_dimensions(&trusted_path)?;
+        return Ok(ComparisonResult {
+            pct_diff: 0.0,
+            threshold: options.threshold,
+            changed_px: 0,
+            antialiased_px: 0,
+            ignored_px: 0,
+            trusted_dimensions: dimensions,
+            testing_dimensions: dimensions,
+            regions: vec![],
+            trusted_path,
+            testing_path,
+            diff_path,
+        });
+    }
+
+    let before = image::open(&trusted_path)?;
+    let after = image::open(&testing_path)?;
+
+    let PixelDiff {
+        pct_diff,
+        changed_px,
+        antialiased_px,
+        ignored_px,
+        regions,
+        diff_img,
+    } = get_pct_diff(&before, &after, options);
+
+    diff_img.save(&diff_path)?;
+
+    let result = ComparisonResult {
+        pct_diff,
+        threshold: options.threshold,
+        changed_px,
+        antialiased_px,
+        ignored_px,
+        trusted_dimensions: before.dimensions(),
+        testing_dimensions: after.dimensions(),
+        regions,
+        trusted_path,
+        testing_path,
+        diff_path,
+    };
+    // Aligned rows already account for a change in height.
+    let size_changed = if options.align_rows {
+        before.width() != after.width()
     } else {
-        let before = image::open(trusted_path)?;
-        let after = image::open(testing_path)?;
+        before.dimensions() != after.dimensions()
+    };
+    if size_changed {
+        Err(CompareError::DimensionMismatch(Box::new(result)))
+    } else if pct_diff > options.threshold {
+        Err(CompareError::ThresholdExceeded(Box::new(result)))
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a 20x10 black image pair where the testing image has one white pixel,
+    /// a 0.5% difference.
+    fn write_half_pct_pair(name: &str) -> (String, String, String) {
+        let dir = std::env::temp_dir();
+        let path = |suffix: &str| format!("{}/nitpx_{}_{}.png", dir.display(), name, suffix);
+        let trusted: RgbaImage = ImageBuffer::from_pixel(20, 10, Rgba([0, 0, 0, 255]));
+        let mut testing = trusted.clone();
+        testing.put_pixel(3, 4, Rgba([255, 255, 255, 255]));
+        trusted.save(path("trusted")).unwrap();
+        testing.save(path("testing")).unwrap();
+        (path("trusted"), path("testing"), path("diff"))
+    }
 
-        let (pct_diff, diff_img) = get_pct_diff(&before, &after);
+    #[test]
+    fn passes_when_diff_is_under_threshold() {
+        let (trusted, testing, diff) = write_half_pct_pair("under_threshold");
+        let options = DiffOptions { threshold: 1.0, ..DiffOptions::default() };
+        let result = compare(trusted, testing, diff, false, &options).unwrap();
+        assert_eq!(result.changed_px, 1);
+        assert_eq!(result.regions, vec![Region { x: 3, y: 4, width: 1, height: 1, pixels: 1 }]);
+    }
+
+    #[test]
+    fn fails_when_diff_is_over_threshold() {
+        let (trusted, testing, diff) = write_half_pct_pair("over_threshold");
+        let options = DiffOptions { threshold: 0.1, ..DiffOptions::default() };
+        let err = compare(trusted, testing, diff, false, &options).unwrap_err();
+        assert!(matches!(err, CompareError::ThresholdExceeded(_)));
+        assert_eq!(err.to_string(), "diff showed a 0.5% difference, 0.1% allowed");
+    }
+
+    #[test]
+    fn ignored_areas_are_left_out_of_the_percentage() {
+        let (trusted, testing, diff) = write_half_pct_pair("ignored_areas");
+        let options = DiffOptions {
+            testing_ignore: vec![Rect { x: 0, y: 0, width: 5, height: 5 }],
+            ..DiffOptions::default()
+        };
+        let result = compare(trusted, testing, diff, false, &options).unwrap();
+        assert_eq!(result.pct_diff, 0.0);
+        assert_eq!(result.ignored_px, 25);
+    }
+
+    #[test]
+    fn missing_screenshot_is_an_io_error() {
+        let missing = format!("{}/nitpx_missing.png", std::env::temp_dir().display());
+        let err = compare(missing.clone(), missing.clone(), missing, false, &DiffOptions::default()).unwrap_err();
+        assert!(matches!(err, CompareError::Io(_)));
+    }
 
-        diff_img.save(&diff_path)?;
-        // TODO: Shouldn't ever be negative, work through why that happens.
-        if pct_diff > 0.00000000000001 || pct_diff < -0.00000000000001 {
-            Err(Box::new(DiffError::new(pct_diff)))
-        } else {
-            Ok(())
+    #[test]
+    fn pixels_within_tolerance_count_as_unchanged() {
+        let dir = std::env::temp_dir();
+        let path = |suffix: &str| format!("{}/nitpx_tolerance_{}.png", dir.display(), suffix);
+        let trusted: RgbaImage = ImageBuffer::from_pixel(20, 10, Rgba([100, 100, 100, 255]));
+        let testing: RgbaImage = ImageBuffer::from_pixel(20, 10, Rgba([101, 100, 100, 255]));
+        trusted.save(path("trusted")).unwrap();
+        testing.save(path("testing")).unwrap();
+
+        for metric in &[DiffMetric::Sum, DiffMetric::Yiq, DiffMetric::Ciede2000] {
+            let options = DiffOptions { metric: *metric, tolerance: 0.01, ..DiffOptions::default() };
+            assert!(compare(path("trusted"), path("testing"), path("diff"), false, &options).is_ok());
         }
+        let strict = DiffOptions::default();
+        assert!(compare(path("trusted"), path("testing"), path("diff"), false, &strict).is_err());
+    }
+
+    #[test]
+    fn antialiased_edges_are_ignored_when_enabled() {
+        let dir = std::env::temp_dir();
+        let path = |suffix: &str| format!("{}/nitpx_antialiasing_{}.png", dir.display(), suffix);
+        let trusted: RgbaImage = ImageBuffer::from_fn(20, 10, |x, _| {
+            if x < 10 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) }
+        });
+        let mut testing = trusted.clone();
+        testing.put_pixel(10, 5, Rgba([128, 128, 128, 255]));
+        trusted.save(path("trusted")).unwrap();
+        testing.save(path("testing")).unwrap();
+
+        let options = DiffOptions { ignore_antialiasing: true, ..DiffOptions::default() };
+        assert!(compare(path("trusted"), path("testing"), path("diff"), false, &options).is_ok());
+        let strict = DiffOptions::default();
+        assert!(compare(path("trusted"), path("testing"), path("diff"), false, &strict).is_err());
+    }
+
+    #[test]
+    fn size_mismatch_fails_and_pads_the_diff() {
+        let dir = std::env::temp_dir();
+        let path = |suffix: &str| format!("{}/nitpx_size_mismatch_{}.png", dir.display(), suffix);
+        let trusted: RgbaImage = ImageBuffer::from_pixel(20, 10, Rgba([0, 0, 0, 255]));
+        let testing: RgbaImage = ImageBuffer::from_pixel(10, 20, Rgba([0, 0, 0, 255]));
+        trusted.save(path("trusted")).unwrap();
+        testing.save(path("testing")).unwrap();
+
+        let options = DiffOptions { threshold: 100.0, ..DiffOptions::default() };
+        let err = compare(path("trusted"), path("testing"), path("diff"), false, &options).unwrap_err();
+        assert!(matches!(err, CompareError::DimensionMismatch(_)));
+        // 150 pixels are in at least one screenshot and 100 of them are only in one.
+        let pct_diff = err.result().unwrap().pct_diff;
+        assert!((pct_diff - 200.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            err.to_string(),
+            format!("trusted screenshot is 20x10 but testing screenshot is 10x20, diff showed a {}% difference", pct_diff),
+        );
+        assert_eq!(image::open(path("diff")).unwrap().dimensions(), (20, 20));
+    }
+
+    #[test]
+    fn aligned_rows_only_count_inserted_content() {
+        let dir = std::env::temp_dir();
+        let path = |suffix: &str| format!("{}/nitpx_align_rows_{}.png", dir.display(), suffix);
+        let trusted: RgbaImage = ImageBuffer::from_fn(10, 20, |_, y| Rgba([y as u8 * 10, 0, 0, 255]));
+        let testing: RgbaImage = ImageBuffer::from_fn(10, 22, |_, y| {
+            if y < 2 { Rgba([0, 0, 255, 255]) } else { Rgba([(y - 2) as u8 * 10, 0, 0, 255]) }
+        });
+        trusted.save(path("trusted")).unwrap();
+        testing.save(path("testing")).unwrap();
+
+        let options = DiffOptions { align_rows: true, threshold: 10.0, ..DiffOptions::default() };
+        assert!(compare(path("trusted"), path("testing"), path("diff"), false, &options).is_ok());
+        assert_eq!(image::open(path("diff")).unwrap().dimensions(), (10, 22));
+
+        let unaligned = DiffOptions { threshold: 100.0, ..DiffOptions::default() };
+        assert!(compare(path("trusted"), path("testing"), path("diff"), false, &unaligned).is_err());
     }
 }
@@ -1,60 +1,231 @@
 extern crate clap;
 
 use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use nitpx;
+use nitpx::config::ViewportProfile;
+use nitpx::offline::ImagePair;
+use nitpx::report::{RouteReport, Status, Summary};
+use nitpx::route_log::RouteLog;
 use colored::*;
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use serde_json;
 
-fn test(url: &String, config: &nitpx::config::Config) -> Result<(), Box<dyn Error>> {
+fn log_comparison(log: &mut RouteLog, result: &nitpx::ComparisonResult) {
+    if result.antialiased_px > 0 {
+        log.println(format_args!("Ignored {} anti-aliased pixels", result.antialiased_px));
+    }
+    for region in &result.regions {
+        log.println(format_args!(
+            "changed region at x: {} y: {} w: {} h: {} ({} px)",
+            region.x, region.y, region.width, region.height, region.pixels,
+        ));
+    }
+    log.println(format_args!("Percent difference {} (threshold {})", result.pct_diff, result.threshold));
+}
+
+/// A captured page or element, and the result of diffing its screenshots.
+type Comparison = (nitpx::browser::Capture, Result<nitpx::ComparisonResult, nitpx::CompareError>);
+
+fn test(
+    url: &String,
+    viewport: &ViewportProfile,
+    config: &nitpx::config::Config,
+    pool: &nitpx::browser::BrowserPool,
+    log: &mut RouteLog,
+) -> Result<Vec<Comparison>, Box<dyn Error>> {
     let slug = url.replace(&config.trusted, "");
-    println!("{}{}{}", "testing \"".underline(), &slug.underline(), "\"".underline());
+    log.println(format_args!(
+        "{}{}{}{}",
+        "testing \"".underline(),
+        &slug.underline(),
+        "\" at ".underline(),
+        viewport.name.underline(),
+    ));
 
     if config.ignored.contains(&slug)
     {
         Err(Box::new(nitpx::SkipError::new()))
     } else {
-        let images_identical = nitpx::browser::capture_snapshots(
+        let captures = nitpx::browser::capture_snapshots(
             config,
             &slug,
+            viewport,
+            pool,
+            log,
         )?;
 
-        let pic_name = nitpx::url_utils::get_name_from_slug(&slug);
-        nitpx::compare(
-            format!("{}/{}_trusted.png", config.screenshots, pic_name),
-            format!("{}/{}_testing.png", config.screenshots, pic_name),
-            format!("{}/{}_diff.png", config.screenshots, pic_name),
-            images_identical,
-        )
+        let mut comparisons = vec![];
+        for capture in captures {
+            if let Some(selector) = &capture.selector {
+                log.println(format_args!("comparing \"{}\"", nitpx::report::element_label(selector, capture.index)));
+            }
+            let mut diff_options = config.diff_options(&slug);
+            diff_options.trusted_ignore.extend(capture.trusted_ignore.iter().cloned());
+            diff_options.testing_ignore.extend(capture.testing_ignore.iter().cloned());
+
+            let result = nitpx::compare(
+                format!("{}/{}_trusted.png", config.screenshots, capture.pic_name),
+                format!("{}/{}_testing.png", config.screenshots, capture.pic_name),
+                format!("{}/{}_diff.png", config.screenshots, capture.pic_name),
+                capture.images_identical,
+                &diff_options,
+            );
+            match &result {
+                Ok(result) => log_comparison(log, result),
+                Err(e) => if let Some(result) = e.result() {
+                    log_comparison(log, result)
+                },
+            }
+            comparisons.push((capture, result));
+        }
+        Ok(comparisons)
+    }
+}
+
+/// Reports and summary lines of a finished route, and its output.
+/// There is one report for the whole page, or one for each captured element.
+struct RouteOutcome {
+    reports: Vec<(RouteReport, String)>,
+    log: RouteLog,
+}
+
+/// The route in quotes, and the viewport it was captured at.
+fn describe(report: &RouteReport) -> String {
+    if report.viewport.is_empty() {
+        format!("\"{}\"", report.label())
+    } else {
+        format!("\"{}\" at {}", report.label(), report.viewport)
+    }
+}
+
+fn pass_summary(report: &RouteReport, result: &nitpx::ComparisonResult) -> String {
+    format!(
+        "{} {} ({}%)\n",
+        "PASS".black().on_green(),
+        describe(report),
+        result.pct_diff,
+    )
+}
+
+fn fail_summary(report: &RouteReport, e: &dyn Error) -> String {
+    let diff_name = Path::new(&report.diff_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!(
+        "{} {} (See \"{}\"): {}\n",
+        "FAIL".black().on_red(),
+        describe(report),
+        diff_name,
+        e
+    )
+}
+
+fn run_route(
+    url: &String,
+    viewport: &ViewportProfile,
+    config: &nitpx::config::Config,
+    pool: &nitpx::browser::BrowserPool,
+) -> RouteOutcome {
+    let slug = url.replace(&config.trusted, "");
+    let threshold = config.threshold_for(&slug);
+    let mut log = RouteLog::new(config.concurrency > 1);
+
+    let reports = match test(url, viewport, config, pool, &mut log) {
+        Ok(comparisons) => comparisons.into_iter().map(|(capture, result)| {
+            let mut report = RouteReport::new(
+                &slug,
+                capture.selector,
+                &viewport.name,
+                &config.screenshots,
+                &capture.pic_name,
+                Status::of(&result),
+                threshold,
+            );
+            report.index = capture.index;
+            report.blocked = capture.blocked;
+            let summary = match &result {
+                Ok(result) => {
+                    report.pct_diff = Some(result.pct_diff);
+                    pass_summary(&report, result)
+                }
+                Err(e) => {
+                    report.pct_diff = e.result().map(|result| result.pct_diff);
+                    report.message = Some(e.to_string());
+                    fail_summary(&report, e)
+                }
+            };
+            (report, summary)
+        }).collect(),
+        Err(e) => {
+            let status = if e.is::<nitpx::SkipError>() { Status::Skipped } else { Status::Errored };
+            let pic_name = nitpx::url_utils::get_pic_name(&slug, &viewport.name);
+            let mut report = RouteReport::new(&slug, None, &viewport.name, &config.screenshots, &pic_name, status, threshold);
+            report.message = Some(e.to_string());
+            let summary = fail_summary(&report, e.as_ref());
+            vec![(report, summary)]
+        }
+    };
+    for (_, summary) in &reports {
+        log.println(summary);
     }
+
+    RouteOutcome { reports, log }
 }
 
-fn run_tests(config: &nitpx::config::Config) -> Result<(), Box<dyn Error>> {
+fn run_tests(config: &nitpx::config::Config) -> Result<Summary, Box<dyn Error>> {
     let urls = nitpx::url_utils::get_urls(config)?;
 
-    let test_results = urls.iter().map(|url| (url, test(url, config)));
+    // Every route is captured at every viewport.
+    let jobs: Vec<(&String, &ViewportProfile)> = urls.iter()
+        .flat_map(|url| config.viewports.iter().map(move |viewport| (url, viewport)))
+        .collect();
+
+    let pool = nitpx::browser::BrowserPool::new(config);
+    let next_job = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<(usize, RouteOutcome)>> = Mutex::new(vec![]);
+    // Each worker takes the next route until none are left, so slow routes don't hold up others.
+    thread::scope(|scope| {
+        for _ in 0..config.concurrency.min(jobs.len()) {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::SeqCst);
+                if index >= jobs.len() {
+                    break;
+                }
+                let (url, viewport) = jobs[index];
+                let mut outcome = run_route(url, viewport, config, &pool);
+                outcome.log.flush();
+                outcomes.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _)| *index);
+    let reports = outcomes.into_iter().flat_map(|(_, outcome)| outcome.reports).collect();
+    let summary = summarize(config, reports)?;
+    summary.write_json(&nitpx::baseline::last_run_path(config))?;
+    Ok(summary)
+}
+
+/// Prints the summary lines of passing and failing reports, and writes the configured summary
+/// and reports.
+fn summarize(config: &nitpx::config::Config, outcomes: Vec<(RouteReport, String)>) -> Result<Summary, Box<dyn Error>> {
+    let mut reports: Vec<RouteReport> = vec![];
     let mut passes: Vec<String> = vec![];
     let mut fails: Vec<String> = vec![];
-    for (url, diff_result) in test_results {
-        let slug = url.replace(&config.trusted, "");
-
-        match diff_result {
-            Ok(_) => {
-                let test_summary = format!("{} \"{}\"\n", "PASS".black().on_green(), slug);
-                println!("{}", test_summary);
-                passes.push(test_summary);
-            }
-            Err(e) => {
-                let test_summary = format!(
-                    "{} (See \"{}\"): {:?}\n",
-                    "FAIL".black().on_red(),
-                    format!("{}_diff.png", nitpx::url_utils::get_name_from_slug(&slug)),
-                    e
-                );
-
-                println!("{}", test_summary);fails.push(test_summary);
-            }
+    for (report, summary) in outcomes {
+        if report.status == Status::Passed {
+            passes.push(summary);
+        } else {
+            fails.push(summary);
         }
+        reports.push(report);
     }
 
     if passes.len() > 0 {
@@ -80,7 +251,44 @@ fn run_tests(config: &nitpx::config::Config) -> Result<(), Box<dyn Error>> {
         }
     }
 
-    Ok(())
+    let summary = Summary::new(reports);
+    if let Some(path) = &config.summary_json {
+        summary.write_json(path)?;
+        println!("Wrote JSON summary to \"{}\"", path);
+    }
+    let html_path = nitpx::html_report::write(&summary, &config.screenshots)?;
+    println!("Wrote HTML report to \"{}\"", html_path);
+    if let (Some(reporter), Some(path)) = (config.reporter, &config.report_path) {
+        summary.write_report(reporter, path)?;
+        println!("Wrote {} report to \"{}\"", reporter, path);
+    }
+    Ok(summary)
+}
+
+/// Diffs each pair of image files without a browser. Files without a match are errors.
+fn run_offline(config: &nitpx::config::Config, pairs: Vec<ImagePair>) -> Result<Summary, Box<dyn Error>> {
+    fs::create_dir_all(&config.screenshots)?;
+    let reports = pairs.iter().map(|pair| match (&pair.trusted, &pair.testing) {
+        (Some(trusted), Some(testing)) => {
+            let (report, result) = nitpx::offline::diff_files(config, &pair.name, trusted, testing);
+            let summary = match &result {
+                Ok(result) => pass_summary(&report, result),
+                Err(e) => fail_summary(&report, e),
+            };
+            (report, summary)
+        }
+        _ => {
+            let report = nitpx::offline::missing_report(config, pair);
+            let summary = format!(
+                "{} {}: {}\n",
+                "FAIL".black().on_red(),
+                describe(&report),
+                report.message.as_deref().unwrap_or(""),
+            );
+            (report, summary)
+        }
+    }).collect();
+    summarize(config, reports)
 }
 
 fn map_match(matches: &ArgMatches, arg:  &str) -> Option<String> {
@@ -127,6 +335,147 @@ fn main() -> Result<(), Box<dyn Error>> {
             .takes_value(true)
             .help("Allowed percent difference between testing and trusted urls before test is considered a fail.")
         )
+        .arg(Arg::with_name("metric")
+            .long("metric")
+            .takes_value(true)
+            .possible_values(&["sum", "yiq", "ciede2000"])
+            .help("How the difference between two pixels is measured. \"sum\" adds the absolute RGB channel differences, \"yiq\" is a perceptual difference like pixelmatch uses, and \"ciede2000\" is the CIE Delta E 2000 color difference.")
+        )
+        .arg(Arg::with_name("tolerance")
+            .long("tolerance")
+            .takes_value(true)
+            .help("Difference from 0 to 1 a single pixel may have under the chosen metric and still count as unchanged.")
+        )
+        .arg(Arg::with_name("ignore_antialiasing")
+            .long("ignore-antialiasing")
+            .help("If present, pixels that look like anti-aliasing differences are highlighted in yellow and not counted as changed")
+        )
+        .arg(Arg::with_name("align_rows")
+            .long("align-rows")
+            .help("If present, align rows of both screenshots like a text diff, so content that only moved up or down is not counted as changed")
+        )
+        .arg(Arg::with_name("ignore_selectors")
+            .long("ignore-selectors")
+            .takes_value(true)
+            .help("Comma separated list of CSS selectors for elements to leave out of the comparison, like ads or timestamps")
+        )
+        .arg(Arg::with_name("capture_selectors")
+            .long("capture-selectors")
+            .takes_value(true)
+            .help("Comma separated list of CSS selectors for elements to screenshot and compare on their own instead of the whole page, reported as route#selector, or route#selector[n] when it matches several")
+        )
+        .arg(Arg::with_name("hide_selectors")
+            .long("hide-selectors")
+            .takes_value(true)
+            .help("Comma separated list of CSS selectors for elements to hide with visibility: hidden before capture")
+        )
+        .arg(Arg::with_name("disable_animations")
+            .long("disable-animations")
+            .help("If present, turn off CSS animations and transitions and hide the text caret before capture")
+        )
+        .arg(Arg::with_name("stabilize_script")
+            .long("stabilize-script")
+            .takes_value(true)
+            .help("Path to a JavaScript file to run in both the trusted and testing pages before waiting for render")
+        )
+        .arg(Arg::with_name("freeze_time")
+            .long("freeze-time")
+            .takes_value(true)
+            .help("Milliseconds since the Unix epoch to freeze Date.now() and new Date() at in both pages, before any page script runs")
+        )
+        .arg(Arg::with_name("random_seed")
+            .long("random-seed")
+            .takes_value(true)
+            .help("Seed for a generator that replaces Math.random in both pages, before any page script runs")
+        )
+        .arg(Arg::with_name("max_browser_uses")
+            .long("max-browser-uses")
+            .takes_value(true)
+            .help("How many routes one browser captures before it is replaced by a newly launched one")
+        )
+        .arg(Arg::with_name("concurrency")
+            .long("concurrency")
+            .takes_value(true)
+            .help("How many routes to capture and diff at the same time, each in its own browser")
+        )
+        .arg(Arg::with_name("simultaneous")
+            .long("simultaneous")
+            .help("If present, load the trusted and testing pages at the same time in two tabs and capture them right after each other")
+        )
+        .arg(Arg::with_name("render_wait_base_ms")
+            .long("render-wait-base-ms")
+            .takes_value(true)
+            .help("Without any --wait-for conditions, milliseconds to wait for every page to render, on top of --render-wait-per-megapixel-ms")
+        )
+        .arg(Arg::with_name("render_wait_per_megapixel_ms")
+            .long("render-wait-per-megapixel-ms")
+            .takes_value(true)
+            .help("Without any --wait-for conditions, milliseconds to wait for every million pixels of page to render")
+        )
+        .arg(Arg::with_name("wait_for_network_idle_ms")
+            .long("wait-for-network-idle-ms")
+            .takes_value(true)
+            .help("Before capture, wait until the page has loaded, no fetch or XHR requests are in flight, and no requests have finished for this many milliseconds")
+        )
+        .arg(Arg::with_name("wait_for_fonts")
+            .long("wait-for-fonts")
+            .help("If present, wait until web fonts have loaded before capture")
+        )
+        .arg(Arg::with_name("wait_for_images")
+            .long("wait-for-images")
+            .help("If present, wait until every image has loaded before capture")
+        )
+        .arg(Arg::with_name("wait_for_selector")
+            .long("wait-for-selector")
+            .takes_value(true)
+            .help("Before capture, wait until an element matching this CSS selector is in the page")
+        )
+        .arg(Arg::with_name("wait_for_selector_gone")
+            .long("wait-for-selector-gone")
+            .takes_value(true)
+            .help("Before capture, wait until no element matching this CSS selector is in the page")
+        )
+        .arg(Arg::with_name("wait_for_script")
+            .long("wait-for-script")
+            .takes_value(true)
+            .help("Before capture, wait until this JavaScript expression is true")
+        )
+        .arg(Arg::with_name("scroll_through")
+            .long("scroll-through")
+            .help("If present, scroll through each page before capture so lazy loaded images load")
+        )
+        .arg(Arg::with_name("tile_height")
+            .long("tile-height")
+            .takes_value(true)
+            .help("Capture pages taller than this many pixels in tiles of this height, stitched together, instead of growing the window")
+        )
+        .arg(Arg::with_name("block_requests")
+            .long("block-requests")
+            .takes_value(true)
+            .help("Comma separated list of URL glob patterns for requests to answer with an empty response in both pages, like analytics or chat widgets. * matches any run of characters and ? any one character")
+        )
+        .arg(Arg::with_name("summary_json")
+            .long("summary-json")
+            .takes_value(true)
+            .help("Path to write a JSON summary to, with every route's status, percent difference and screenshot paths")
+        )
+        .arg(Arg::with_name("reporter")
+            .long("reporter")
+            .takes_value(true)
+            .possible_values(&["junit"])
+            .requires("report_path")
+            .help("Format of a report to write to --report-path. \"junit\" writes JUnit XML for CI dashboards")
+        )
+        .arg(Arg::with_name("report_path")
+            .long("report-path")
+            .takes_value(true)
+            .help("Path to write the --reporter report to")
+        )
+        .arg(Arg::with_name("baselines")
+            .long("baselines")
+            .takes_value(true)
+            .help("Path to a folder of approved screenshots to compare the testing site against, instead of loading the trusted site")
+        )
         .arg(Arg::with_name("testing")
             .long("testing")
             .takes_value(true)
@@ -141,6 +490,23 @@ fn main() -> Result<(), Box<dyn Error>> {
             .long("log-config")
             .help("log the computed config to stdout as JSON, then exit. Useful for debugging what config value is actually used and for sharing computed configs.")
         )
+        .subcommand(SubCommand::with_name("approve")
+            .about("Copies testing screenshots from the last run into the --baselines folder, so later runs are compared against them")
+            .arg(Arg::with_name("slugs")
+                .multiple(true)
+                .help("Route slugs to approve at every viewport. Without any, every route that failed or errored in the last run is approved")
+            )
+        )
+        .subcommand(SubCommand::with_name("diff")
+            .about("Diffs two image files without a browser, writing the diff and reports to the --screenshots folder")
+            .arg(Arg::with_name("trusted_image").required(true).help("Path to the trusted image"))
+            .arg(Arg::with_name("testing_image").required(true).help("Path to the testing image"))
+        )
+        .subcommand(SubCommand::with_name("diff-dir")
+            .about("Diffs the images of two folders without a browser, matching them by file name")
+            .arg(Arg::with_name("trusted_dir").required(true).help("Path to the folder of trusted images"))
+            .arg(Arg::with_name("testing_dir").required(true).help("Path to the folder of testing images"))
+        )
         .get_matches();
 
 
@@ -151,6 +517,36 @@ fn main() -> Result<(), Box<dyn Error>> {
         routes: map_match(&cli_result, "routes"),
         screenshots: map_match(&cli_result, "screenshots"),
         threshold: map_match(&cli_result, "threshold"),
+        metric: map_match(&cli_result, "metric"),
+        tolerance: map_match(&cli_result, "tolerance"),
+        ignore_antialiasing: cli_result.is_present("ignore_antialiasing"),
+        align_rows: cli_result.is_present("align_rows"),
+        ignore_selectors: map_match(&cli_result, "ignore_selectors"),
+        capture_selectors: map_match(&cli_result, "capture_selectors"),
+        hide_selectors: map_match(&cli_result, "hide_selectors"),
+        disable_animations: cli_result.is_present("disable_animations"),
+        stabilize_script: map_match(&cli_result, "stabilize_script"),
+        freeze_time: map_match(&cli_result, "freeze_time"),
+        random_seed: map_match(&cli_result, "random_seed"),
+        max_browser_uses: map_match(&cli_result, "max_browser_uses"),
+        concurrency: map_match(&cli_result, "concurrency"),
+        simultaneous: cli_result.is_present("simultaneous"),
+        render_wait_base_ms: map_match(&cli_result, "render_wait_base_ms"),
+        render_wait_per_megapixel_ms: map_match(&cli_result, "render_wait_per_megapixel_ms"),
+        wait_for_network_idle_ms: map_match(&cli_result, "wait_for_network_idle_ms"),
+        wait_for_fonts: cli_result.is_present("wait_for_fonts"),
+        wait_for_images: cli_result.is_present("wait_for_images"),
+        wait_for_selector: map_match(&cli_result, "wait_for_selector"),
+        wait_for_selector_gone: map_match(&cli_result, "wait_for_selector_gone"),
+        wait_for_script: map_match(&cli_result, "wait_for_script"),
+        scroll_through: cli_result.is_present("scroll_through"),
+        tile_height: map_match(&cli_result, "tile_height"),
+        block_requests: map_match(&cli_result, "block_requests"),
+        summary_json: map_match(&cli_result, "summary_json"),
+        reporter: map_match(&cli_result, "reporter"),
+        report_path: map_match(&cli_result, "report_path"),
+        baselines: map_match(&cli_result, "baselines"),
+        offline: matches!(cli_result.subcommand_name(), Some("diff") | Some("diff-dir")),
         testing: map_match(&cli_result, "testing"),
         trusted: map_match(&cli_result, "trusted"),
     };
@@ -166,7 +562,39 @@ fn main() -> Result<(), Box<dyn Error>> {
         std::process::exit(1);
     }
 
-    run_tests(&config)?;
+    if let Some(approve) = cli_result.subcommand_matches("approve") {
+        let slugs: Vec<String> = approve.values_of("slugs")
+            .map(|slugs| slugs.map(String::from).collect())
+            .unwrap_or_default();
+        let approved = nitpx::baseline::approve(&config, &slugs)?;
+        for path in &approved {
+            println!("Approved \"{}\"", path);
+        }
+        println!("Approved {} screenshots", approved.len());
+        return Ok(());
+    }
+
+    let summary = match cli_result.subcommand() {
+        ("diff", Some(diff)) => {
+            let trusted = diff.value_of("trusted_image").unwrap_or_default();
+            let testing = diff.value_of("testing_image").unwrap_or_default();
+            run_offline(&config, vec![ImagePair {
+                name: nitpx::offline::image_name(trusted),
+                trusted: Some(String::from(trusted)),
+                testing: Some(String::from(testing)),
+            }])?
+        }
+        ("diff-dir", Some(diff_dir)) => {
+            let trusted_dir = diff_dir.value_of("trusted_dir").unwrap_or_default();
+            let testing_dir = diff_dir.value_of("testing_dir").unwrap_or_default();
+            run_offline(&config, nitpx::offline::match_files(trusted_dir, testing_dir)?)?
+        }
+        _ => run_tests(&config)?,
+    };
+    let exit_code = summary.exit_code();
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 
     Ok(())
 }
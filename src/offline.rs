@@ -0,0 +1,134 @@
+//! Diffing image files without a browser, like exports from design tools or screenshots taken
+//! by other tools.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::report::{RouteReport, Status};
+use crate::{compare, CompareError, ComparisonResult};
+
+/// Extensions of the files `diff-dir` compares.
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "bmp"];
+
+/// Files with the same name in the trusted and testing folders. Either side is `None` when the
+/// file is only in the other folder.
+#[derive(Debug, PartialEq)]
+pub struct ImagePair {
+    pub name: String,
+    pub trusted: Option<String>,
+    pub testing: Option<String>,
+}
+
+/// Name of an image without its folder, used as its route in reports and for its diff file.
+/// The extension is kept, so `home.png` and `home.jpg` don't share a diff.
+pub fn image_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn image_files(dir: &str) -> io::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_image = path.extension()
+            .map(|extension| IMAGE_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+            .unwrap_or(false);
+        if path.is_file() && is_image {
+            if let Some(name) = path.file_name() {
+                names.insert(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Pairs up the images of both folders by file name, sorted by name.
+pub fn match_files(trusted_dir: &str, testing_dir: &str) -> io::Result<Vec<ImagePair>> {
+    let trusted = image_files(trusted_dir)?;
+    let testing = image_files(testing_dir)?;
+    Ok(trusted.union(&testing)
+        .map(|file_name| ImagePair {
+            name: file_name.clone(),
+            trusted: Some(format!("{}/{}", trusted_dir, file_name)).filter(|_| trusted.contains(file_name)),
+            testing: Some(format!("{}/{}", testing_dir, file_name)).filter(|_| testing.contains(file_name)),
+        })
+        .collect())
+}
+
+/// Diffs `trusted` against `testing`, writing the diff to `{name}_diff.png` in the screenshots
+/// folder. `name` also picks up any `overrides` of threshold and ignored regions.
+pub fn diff_files(
+    config: &Config,
+    name: &str,
+    trusted: &str,
+    testing: &str,
+) -> (RouteReport, Result<ComparisonResult, CompareError>) {
+    let mut report = RouteReport::new(name, None, "", &config.screenshots, name, Status::Errored, config.threshold_for(name));
+    report.trusted_path = trusted.to_string();
+    report.testing_path = testing.to_string();
+
+    let result = compare(
+        report.trusted_path.clone(),
+        report.testing_path.clone(),
+        report.diff_path.clone(),
+        false,
+        &config.diff_options(name),
+    );
+    report.status = Status::of(&result);
+    match &result {
+        Ok(result) => report.pct_diff = Some(result.pct_diff),
+        Err(e) => {
+            report.pct_diff = e.result().map(|result| result.pct_diff);
+            report.message = Some(e.to_string());
+        }
+    }
+    (report, result)
+}
+
+/// Report of a file that is only in one of the folders.
+pub fn missing_report(config: &Config, pair: &ImagePair) -> RouteReport {
+    let mut report = RouteReport::new(&pair.name, None, "", &config.screenshots, &pair.name, Status::Errored, config.threshold_for(&pair.name));
+    let (found, side) = match (&pair.trusted, &pair.testing) {
+        (Some(trusted), _) => (trusted, "testing"),
+        (None, Some(testing)) => (testing, "trusted"),
+        (None, None) => return report,
+    };
+    report.message = Some(format!("\"{}\" has no match in the {} folder", found, side));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_images_by_file_name() {
+        let root = std::env::temp_dir().join(format!("nitpx-offline-{}", std::process::id()));
+        let (trusted, testing) = (root.join("trusted"), root.join("testing"));
+        fs::create_dir_all(&trusted).unwrap();
+        fs::create_dir_all(&testing).unwrap();
+        for (dir, file) in &[(&trusted, "home.png"), (&testing, "home.png"), (&testing, "home.jpg"), (&trusted, "old.png"), (&testing, "new.jpg"), (&testing, "notes.txt")] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        let (trusted, testing) = (trusted.to_string_lossy().into_owned(), testing.to_string_lossy().into_owned());
+
+        let pairs = match_files(&trusted, &testing).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(pairs, vec![
+            ImagePair { name: String::from("home.jpg"), trusted: None, testing: Some(format!("{}/home.jpg", testing)) },
+            ImagePair {
+                name: String::from("home.png"),
+                trusted: Some(format!("{}/home.png", trusted)),
+                testing: Some(format!("{}/home.png", testing)),
+            },
+            ImagePair { name: String::from("new.jpg"), trusted: None, testing: Some(format!("{}/new.jpg", testing)) },
+            ImagePair { name: String::from("old.png"), trusted: Some(format!("{}/old.png", trusted)), testing: None },
+        ]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::time::Duration;
+
+use headless_chrome::{util::Wait, Tab};
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// How long a page gets to meet all readiness conditions before its route fails.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often readiness conditions are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A JavaScript expression that is true once the page is ready, and a description for errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub description: String,
+    pub expression: String,
+}
+
+/// Run at document start, counts fetch and XHR requests still in flight. headless_chrome drops
+/// the `Network.requestWillBeSent` and `Network.loadingFinished` events, so in-flight requests
+/// can't be followed from here.
+const REQUEST_TRACKING_JS: &str = "(function() {
+    var tracker = window.__nitpxRequests = { inFlight: 0 };
+    var done = function() { tracker.inFlight--; };
+    var realFetch = window.fetch;
+    if (realFetch) {
+        window.fetch = function() {
+            tracker.inFlight++;
+            try {
+                var response = realFetch.apply(this, arguments);
+            } catch (e) {
+                done();
+                throw e;
+            }
+            response.then(done, done);
+            return response;
+        };
+    }
+    var realSend = XMLHttpRequest.prototype.send;
+    XMLHttpRequest.prototype.send = function() {
+        tracker.inFlight++;
+        this.addEventListener(\"loadend\", done, { once: true });
+        try {
+            return realSend.apply(this, arguments);
+        } catch (e) {
+            done();
+            throw e;
+        }
+    };
+})();";
+
+/// Script to register at document start so readiness conditions can see requests in flight.
+/// Returns `None` when no condition needs it.
+pub fn request_tracking_script(config: &Config) -> Option<String> {
+    config.wait_for_network_idle_ms.map(|_| String::from(REQUEST_TRACKING_JS))
+}
+
+/// The network is busy while the document and its subresources are still loading, or while a
+/// fetch or XHR request is in flight. Other requests are seen through resource timing entries,
+/// which are only added once a request finishes. The buffer is enlarged so busy pages keep
+/// adding entries.
+fn network_idle_expression(idle_ms: u64) -> String {
+    format!("(function() {{
+        if (!window.__nitpxNetworkIdle) {{
+            performance.setResourceTimingBufferSize(1000000);
+            window.__nitpxNetworkIdle = {{ count: -1, since: 0 }};
+        }}
+        var state = window.__nitpxNetworkIdle;
+        var count = performance.getEntriesByType(\"resource\").length;
+        var busy = document.readyState !== \"complete\"
+            || (window.__nitpxRequests !== undefined && window.__nitpxRequests.inFlight > 0);
+        var now = performance.now();
+        if (count !== state.count || busy) {{
+            state.count = count;
+            state.since = now;
+        }}
+        return now - state.since >= {};
+    }})()", idle_ms)
+}
+
+/// Readiness conditions configured in `config`, in the order they are waited on.
+pub fn conditions(config: &Config) -> Vec<Condition> {
+    let mut conditions = vec![];
+    if let Some(idle_ms) = config.wait_for_network_idle_ms {
+        conditions.push(Condition {
+            description: format!("no requests in flight or finishing for {} ms", idle_ms),
+            expression: network_idle_expression(idle_ms),
+        });
+    }
+    if config.wait_for_fonts {
+        conditions.push(Condition {
+            description: String::from("fonts loaded"),
+            expression: String::from("document.fonts.status === \"loaded\""),
+        });
+    }
+    if config.wait_for_images {
+        conditions.push(Condition {
+            description: String::from("images loaded"),
+            expression: String::from(
+                "Array.prototype.every.call(document.images, function(img) { return img.complete; })",
+            ),
+        });
+    }
+    if let Some(selector) = &config.wait_for_selector {
+        conditions.push(Condition {
+            description: format!("\"{}\" present", selector),
+            expression: format!("document.querySelector({}) !== null", Value::from(selector.as_str())),
+        });
+    }
+    if let Some(selector) = &config.wait_for_selector_gone {
+        conditions.push(Condition {
+            description: format!("\"{}\" gone", selector),
+            expression: format!("document.querySelector({}) === null", Value::from(selector.as_str())),
+        });
+    }
+    if let Some(script) = &config.wait_for_script {
+        conditions.push(Condition {
+            description: String::from("wait_for_script returning true"),
+            expression: format!("Boolean(eval({}))", Value::from(script.as_str())),
+        });
+    }
+    conditions
+}
+
+/// Polls each condition in `tab` until it is true.
+///
+/// Errors while evaluating, e.g. from a page still navigating, count as not ready yet.
+pub fn wait_until_ready(tab: &Tab, conditions: &[Condition]) -> Result<(), Box<dyn Error>> {
+    for condition in conditions {
+        Wait::new(READY_TIMEOUT, POLL_INTERVAL)
+            .until(|| match tab.evaluate(&condition.expression, false) {
+                Ok(result) if result.value == Some(Value::Bool(true)) => Some(()),
+                _ => None,
+            })
+            .map_err(|_| format!("Page not ready after {:?}, waiting for {}", READY_TIMEOUT, condition.description))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_configured_conditions_are_waited_on() {
+        let config: Config = serde_json::from_str(r#"{
+            "headless": true,
+            "ignored": [],
+            "routes": "blog",
+            "screenshots": "/tmp",
+            "threshold": 0.0,
+            "testing": "http://localhost:8081/",
+            "trusted": "http://localhost:8080/",
+            "wait_for_fonts": true,
+            "wait_for_selector": "div[data-ready=\"yes\"]"
+        }"#).unwrap();
+
+        let conditions = conditions(&config);
+        assert_eq!(request_tracking_script(&config), None);
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].expression, "document.fonts.status === \"loaded\"");
+        assert_eq!(
+            conditions[1].expression,
+            r#"document.querySelector("div[data-ready=\"yes\"]") !== null"#,
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use image::{Rgba, RgbaImage};
+
+/// Changed pixels this close together (in pixels) are grouped into the same region.
+const CELL_SIZE: u32 = 8;
+
+/// Thickness in pixels of the outline drawn around each region.
+const OUTLINE_WIDTH: u32 = 2;
+
+/// A rectangle in screenshot pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+}
+
+/// Bounding box around a group of changed pixels in the diff image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Number of changed pixels inside the box.
+    pub pixels: u64,
+}
+
+/// Groups the changed pixels in `mask` (row major, `width` by `height`) into connected regions,
+/// ordered top to bottom.
+///
+/// The mask is split into `CELL_SIZE` square cells, and touching cells with changes are joined,
+/// so changes separated by a few unchanged pixels, like letters in a word, land in one region.
+pub fn find_regions(mask: &[bool], width: u32, height: u32) -> Vec<Region> {
+    let cols = width.div_ceil(CELL_SIZE);
+    let rows = height.div_ceil(CELL_SIZE);
+
+    let mut cell_pixels = vec![0u64; (cols * rows) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            if mask[(y * width + x) as usize] {
+                cell_pixels[((y / CELL_SIZE) * cols + x / CELL_SIZE) as usize] += 1;
+            }
+        }
+    }
+
+    let mut seen = vec![false; cell_pixels.len()];
+    let mut regions = vec![];
+    for start in 0..cell_pixels.len() {
+        if seen[start] || cell_pixels[start] == 0 {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let mut cells = vec![];
+        while let Some(cell) = stack.pop() {
+            cells.push(cell);
+            let (cx, cy) = ((cell as u32 % cols) as i64, (cell as u32 / cols) as i64);
+            for ny in cy - 1..=cy + 1 {
+                for nx in cx - 1..=cx + 1 {
+                    if nx < 0 || ny < 0 || nx >= cols as i64 || ny >= rows as i64 {
+                        continue;
+                    }
+                    let neighbour = (ny * cols as i64 + nx) as usize;
+                    if !seen[neighbour] && cell_pixels[neighbour] > 0 {
+                        seen[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        regions.push(bounding_box(mask, width, height, cols, &cells, &cell_pixels));
+    }
+
+    regions.sort_by_key(|region| (region.y, region.x));
+    regions
+}
+
+/// Tight bounding box of the changed pixels within `cells`.
+fn bounding_box(
+    mask: &[bool],
+    width: u32,
+    height: u32,
+    cols: u32,
+    cells: &[usize],
+    cell_pixels: &[u64],
+) -> Region {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for &cell in cells {
+        let x0 = (cell as u32 % cols) * CELL_SIZE;
+        let y0 = (cell as u32 / cols) * CELL_SIZE;
+        for y in y0..(y0 + CELL_SIZE).min(height) {
+            for x in x0..(x0 + CELL_SIZE).min(width) {
+                if mask[(y * width + x) as usize] {
+                    min_x = min_x.min(x);
+                    min_y = min_y.min(y);
+                    max_x = max_x.max(x);
+                    max_y = max_y.max(y);
+                }
+            }
+        }
+    }
+    Region {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+        pixels: cells.iter().map(|&cell| cell_pixels[cell]).sum(),
+    }
+}
+
+/// Draws a red outline just outside each region.
+pub fn draw_outlines(img: &mut RgbaImage, regions: &[Region]) {
+    let outline_rgba = Rgba([255, 0, 0, 255]);
+    let (width, height) = img.dimensions();
+    for region in regions {
+        let left = region.x.saturating_sub(OUTLINE_WIDTH);
+        let top = region.y.saturating_sub(OUTLINE_WIDTH);
+        let right = (region.x + region.width + OUTLINE_WIDTH).min(width);
+        let bottom = (region.y + region.height + OUTLINE_WIDTH).min(height);
+        for y in top..bottom {
+            for x in left..right {
+                let inside = x >= region.x && x < region.x + region.width
+                    && y >= region.y && y < region.y + region.height;
+                if !inside {
+                    img.put_pixel(x, y, outline_rgba);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separate_changes_become_separate_regions() {
+        let (width, height) = (40, 40);
+        let mut mask = vec![false; (width * height) as usize];
+        for &(x, y) in &[(2, 3), (4, 5), (30, 30), (31, 33)] {
+            mask[(y * width + x) as usize] = true;
+        }
+
+        assert_eq!(find_regions(&mask, width, height), vec![
+            Region { x: 2, y: 3, width: 3, height: 3, pixels: 2 },
+            Region { x: 30, y: 30, width: 2, height: 4, pixels: 2 },
+        ]);
+    }
+}
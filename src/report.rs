@@ -0,0 +1,308 @@
+//! Machine readable results of a run, for CI to gate on.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+use serde_json;
+
+use crate::{CompareError, ComparisonResult};
+
+/// Exit code when any route differed by more than its threshold, or changed size.
+/// Config errors and errors that stop a run exit with 1, so CI can tell them apart.
+pub const EXIT_FAILED: i32 = 3;
+
+/// Exit code when nothing failed, but some routes were skipped or could not be captured.
+pub const EXIT_INCOMPLETE: i32 = 2;
+
+/// Format of the report written to `report_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reporter {
+    /// A JUnit XML `testsuite`, with a `testcase` for every route at every viewport.
+    Junit,
+}
+
+impl fmt::Display for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Reporter::Junit => "junit",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Reporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Reporter, String> {
+        match s.trim().to_lowercase().as_str() {
+            "junit" => Ok(Reporter::Junit),
+            other => Err(format!("Unknown reporter \"{}\". Expected junit", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Within the allowed threshold.
+    Passed,
+    /// Differed by more than the allowed threshold, or changed size.
+    Failed,
+    /// Listed in `ignored`.
+    Skipped,
+    /// Could not be captured or compared.
+    Errored,
+}
+
+impl Status {
+    /// Status of a comparison that ran.
+    pub fn of(result: &Result<ComparisonResult, CompareError>) -> Status {
+        match result {
+            Ok(_) => Status::Passed,
+            Err(e) if e.result().is_some() => Status::Failed,
+            Err(_) => Status::Errored,
+        }
+    }
+}
+
+/// `selector`, or `selector[n]` for the element at `index` of several matching it.
+pub fn element_label(selector: &str, index: Option<usize>) -> String {
+    match index {
+        Some(index) => format!("{}[{}]", selector, index),
+        None => selector.to_string(),
+    }
+}
+
+/// Result of one route, or one element of a route, at one viewport.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteReport {
+    pub route: String,
+    /// The `capture_selectors` entry that was compared, or `None` for the whole page.
+    pub selector: Option<String>,
+    /// Which of the elements matching `selector` was compared, when it matched more than one.
+    #[serde(default)]
+    pub index: Option<usize>,
+    pub viewport: String,
+    pub status: Status,
+    /// Percent difference, when the screenshots could be diffed.
+    pub pct_diff: Option<f64>,
+    /// Percent difference that was allowed.
+    pub threshold: f64,
+    pub trusted_path: String,
+    pub testing_path: String,
+    pub diff_path: String,
+    /// Why the route didn't pass.
+    pub message: Option<String>,
+    /// Requests matching `block_requests` that the route's pages made. Only the first report of a
+    /// route with `capture_selectors` counts them.
+    #[serde(default)]
+    pub blocked: usize,
+}
+
+impl RouteReport {
+    /// A report without a diff, with screenshot paths for `pic_name` in `screenshots`.
+    pub fn new(
+        route: &str,
+        selector: Option<String>,
+        viewport: &str,
+        screenshots: &str,
+        pic_name: &str,
+        status: Status,
+        threshold: f64,
+    ) -> RouteReport {
+        RouteReport {
+            route: route.to_string(),
+            selector,
+            index: None,
+            viewport: viewport.to_string(),
+            status,
+            pct_diff: None,
+            threshold,
+            trusted_path: format!("{}/{}_trusted.png", screenshots, pic_name),
+            testing_path: format!("{}/{}_testing.png", screenshots, pic_name),
+            diff_path: format!("{}/{}_diff.png", screenshots, pic_name),
+            message: None,
+            blocked: 0,
+        }
+    }
+
+    /// The route as shown in the summary, with elements as `route#selector`, or as
+    /// `route#selector[n]` when the selector matched more than one.
+    pub fn label(&self) -> String {
+        match &self.selector {
+            Some(selector) => format!("{}#{}", self.route, element_label(selector, self.index)),
+            None => self.route.clone(),
+        }
+    }
+
+    /// The label and the viewport it was captured at. Images diffed offline have no viewport.
+    pub fn name(&self) -> String {
+        if self.viewport.is_empty() {
+            self.label()
+        } else {
+            format!("{} at {}", self.label(), self.viewport)
+        }
+    }
+}
+
+/// Counts of each status, and every route's report in the order routes were listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub errored: usize,
+    pub routes: Vec<RouteReport>,
+}
+
+impl Summary {
+    pub fn new(routes: Vec<RouteReport>) -> Summary {
+        let count = |status| routes.iter().filter(|route| route.status == status).count();
+        Summary {
+            passed: count(Status::Passed),
+            failed: count(Status::Failed),
+            skipped: count(Status::Skipped),
+            errored: count(Status::Errored),
+            routes,
+        }
+    }
+
+    /// 0 when every route passed, `EXIT_FAILED` when any failed, and otherwise
+    /// `EXIT_INCOMPLETE` when any were skipped or errored.
+    pub fn exit_code(&self) -> i32 {
+        if self.failed > 0 {
+            EXIT_FAILED
+        } else if self.skipped > 0 || self.errored > 0 {
+            EXIT_INCOMPLETE
+        } else {
+            0
+        }
+    }
+
+    pub fn write_json(&self, path: &str) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// The summary as a JUnit XML `testsuite` named `nitpx`.
+    ///
+    /// Failures carry the percent difference and the path to the diff image, and routes listed in
+    /// `ignored` are `<skipped/>`.
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"nitpx\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\">\n",
+            self.routes.len(),
+            self.failed,
+            self.errored,
+            self.skipped,
+        ));
+        for route in &self.routes {
+            let classname = if route.viewport.is_empty() {
+                String::from("nitpx")
+            } else {
+                format!("nitpx.{}", route.viewport)
+            };
+            xml.push_str(&format!(
+                "  <testcase classname=\"{}\" name=\"{}\"",
+                xml_escape(&classname),
+                xml_escape(&route.name()),
+            ));
+            let message = xml_escape(route.message.as_deref().unwrap_or(""));
+            let mut children = String::new();
+            if route.blocked > 0 {
+                children.push_str(&format!(
+                    "    <properties>\n      <property name=\"blocked_requests\" value=\"{}\"/>\n    </properties>\n",
+                    route.blocked,
+                ));
+            }
+            match route.status {
+                Status::Passed => {}
+                Status::Failed => children.push_str(&format!(
+                    "    <failure message=\"{}\">{}% difference, {}% allowed. See {}</failure>\n",
+                    message,
+                    route.pct_diff.unwrap_or(100.0),
+                    route.threshold,
+                    xml_escape(&route.diff_path),
+                )),
+                Status::Skipped => children.push_str("    <skipped/>\n"),
+                Status::Errored => children.push_str(&format!("    <error message=\"{}\"/>\n", message)),
+            }
+            if children.is_empty() {
+                xml.push_str("/>\n");
+            } else {
+                xml.push_str(&format!(">\n{}  </testcase>\n", children));
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+
+    /// Writes the summary to `path` in the format of `reporter`.
+    pub fn write_report(&self, reporter: Reporter, path: &str) -> io::Result<()> {
+        match reporter {
+            Reporter::Junit => fs::write(path, self.to_junit()),
+        }
+    }
+}
+
+/// Escapes `text` for use in XML and HTML attributes and text.
+pub(crate) fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(route: &str, status: Status) -> RouteReport {
+        RouteReport::new(route, None, "desktop", "/tmp", route, status, 0.0)
+    }
+
+    #[test]
+    fn failures_decide_the_exit_code_before_skips_and_errors() {
+        assert_eq!(Summary::new(vec![report("a", Status::Passed)]).exit_code(), 0);
+        assert_eq!(Summary::new(vec![]).exit_code(), 0);
+
+        let incomplete = Summary::new(vec![report("a", Status::Passed), report("b", Status::Skipped)]);
+        assert_eq!(incomplete.exit_code(), EXIT_INCOMPLETE);
+
+        let failed = Summary::new(vec![
+            report("a", Status::Errored),
+            report("b", Status::Failed),
+            report("c", Status::Failed),
+        ]);
+        assert_eq!((failed.failed, failed.errored), (2, 1));
+        assert_eq!(failed.exit_code(), EXIT_FAILED);
+    }
+
+    #[test]
+    fn junit_reports_failures_with_the_diff_and_skips() {
+        let mut failed = report("blog", Status::Failed);
+        failed.selector = Some(String::from("#nav > a"));
+        failed.pct_diff = Some(2.5);
+        failed.message = Some(String::from("diff showed a 2.5% difference, 0% allowed"));
+
+        let mut ads = report("ads", Status::Passed);
+        ads.blocked = 3;
+
+        let mut item = report("list", Status::Passed);
+        item.selector = Some(String::from("li"));
+        item.index = Some(1);
+
+        let junit = Summary::new(vec![report("about", Status::Passed), failed, report("huge", Status::Skipped), ads, item]).to_junit();
+        assert!(junit.contains("<testsuite name=\"nitpx\" tests=\"5\" failures=\"1\" errors=\"0\" skipped=\"1\">"));
+        assert!(junit.contains("<testcase classname=\"nitpx.desktop\" name=\"about at desktop\"/>"));
+        assert!(junit.contains("name=\"blog##nav &gt; a at desktop\">"));
+        assert!(junit.contains(">2.5% difference, 0% allowed. See /tmp/blog_diff.png</failure>"));
+        assert!(junit.contains("name=\"huge at desktop\">\n    <skipped/>"));
+        assert!(junit.contains("name=\"ads at desktop\">\n    <properties>\n      <property name=\"blocked_requests\" value=\"3\"/>"));
+        assert!(junit.contains("name=\"list#li[1] at desktop\"/>"));
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+/// Output for a single route.
+///
+/// When buffered, lines are held until `flush` and then printed together, so routes running at
+/// the same time don't interleave their output. Otherwise lines are printed right away.
+#[derive(Debug)]
+pub struct RouteLog {
+    buffered: bool,
+    lines: Vec<String>,
+}
+
+impl RouteLog {
+    pub fn new(buffered: bool) -> RouteLog {
+        RouteLog {
+            buffered,
+            lines: vec![],
+        }
+    }
+
+    pub fn println(&mut self, line: impl fmt::Display) {
+        if self.buffered {
+            self.lines.push(line.to_string());
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    /// Prints every held line in one go.
+    pub fn flush(&mut self) {
+        // Holding the lock keeps other threads from printing in between.
+        let _stdout = io::stdout().lock();
+        for line in self.lines.drain(..) {
+            println!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_lines_are_held_until_flush() {
+        let mut log = RouteLog::new(true);
+        log.println("capturing image...");
+        log.println(format_args!("pic length: {}", 42));
+        assert_eq!(log.lines, vec!["capturing image...", "pic length: 42"]);
+
+        log.flush();
+        assert!(log.lines.is_empty());
+    }
+}
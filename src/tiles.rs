@@ -0,0 +1,66 @@
+use image::{ColorType, GenericImage, ImageError, RgbaImage};
+
+/// Top offset and height of each tile needed to cover `total_height`, at most `tile_height` each.
+pub fn tile_offsets(total_height: u32, tile_height: u32) -> Vec<(u32, u32)> {
+    let tile_height = tile_height.max(1);
+    (0..total_height.div_ceil(tile_height))
+        .map(|i| {
+            let top = i * tile_height;
+            (top, tile_height.min(total_height - top))
+        })
+        .collect()
+}
+
+/// Stacks PNG `tiles` top to bottom into a single PNG, as wide as the widest tile.
+pub fn stitch(tiles: &[Vec<u8>]) -> Result<Vec<u8>, ImageError> {
+    let mut images = vec![];
+    for tile in tiles {
+        images.push(image::load_from_memory(tile)?.to_rgba());
+    }
+    let width = images.iter().map(|img| img.width()).max().unwrap_or(0);
+    let height = images.iter().map(|img| img.height()).sum();
+
+    let mut stitched = RgbaImage::new(width, height);
+    let mut top = 0;
+    for img in &images {
+        stitched.copy_from(img, 0, top);
+        top += img.height();
+    }
+
+    let mut png = vec![];
+    image::png::PNGEncoder::new(&mut png).encode(&stitched, width, height, ColorType::RGBA(8))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgba};
+
+    fn png(img: RgbaImage) -> Vec<u8> {
+        let mut bytes = vec![];
+        DynamicImage::ImageRgba8(img).write_to(&mut bytes, ImageOutputFormat::PNG).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn tiles_cover_the_page_with_a_shorter_last_tile() {
+        assert_eq!(tile_offsets(2500, 1000), vec![(0, 1000), (1000, 1000), (2000, 500)]);
+        assert_eq!(tile_offsets(1000, 1000), vec![(0, 1000)]);
+    }
+
+    #[test]
+    fn stitched_tiles_are_stacked_in_order() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let tiles = vec![
+            png(ImageBuffer::from_pixel(4, 3, red)),
+            png(ImageBuffer::from_pixel(4, 2, blue)),
+        ];
+
+        let stitched = image::load_from_memory(&stitch(&tiles).unwrap()).unwrap().to_rgba();
+        assert_eq!(stitched.dimensions(), (4, 5));
+        assert_eq!(*stitched.get_pixel(0, 2), red);
+        assert_eq!(*stitched.get_pixel(3, 3), blue);
+    }
+}
@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::fs;
 use ureq;
 use serde_xml_rs;
 
@@ -50,6 +51,54 @@ pub fn get_name_from_slug(slug: &String) -> String {
     }
 }
 
+/// Base name of the screenshot files for `slug` captured at the viewport named `viewport_name`.
+pub fn get_pic_name(slug: &str, viewport_name: &str) -> String {
+    format!("{}_{}", get_name_from_slug(&slug.to_string()), viewport_name)
+}
+
+/// Base name of the screenshot files for the element matching `selector` on `slug`, or with
+/// `index`, for that one of the elements matching it.
+/// Characters that don't belong in file names are replaced with underscores, and a short hash of
+/// the selector keeps selectors that only differ in those characters apart.
+pub fn get_element_pic_name(slug: &str, viewport_name: &str, selector: &str, index: Option<usize>) -> String {
+    let element_name: String = selector
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let hash = format!("{:x}", md5::compute(selector));
+    let pic_name = format!("{}_{}_{}", get_pic_name(slug, viewport_name), element_name.trim_matches('_'), &hash[..6]);
+    match index {
+        Some(index) => format!("{}_{}", pic_name, index),
+        None => pic_name,
+    }
+}
+
+/// Base names of the files in `dir` named `{pic_name}_{index}{suffix}`, the screenshots of each
+/// of several elements matching the selector `pic_name` is named after.
+pub fn find_indexed_pic_names(dir: &str, pic_name: &str, suffix: &str) -> Vec<String> {
+    let prefix = format!("{}_", pic_name);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut pic_names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|file_name| {
+            let index = file_name.strip_prefix(&prefix)?.strip_suffix(suffix)?;
+            let is_index = !index.is_empty() && index.chars().all(|c| c.is_ascii_digit());
+            Some(format!("{}{}", prefix, index)).filter(|_| is_index)
+        })
+        .collect();
+    pic_names.sort();
+    pic_names
+}
+
+/// Path of the approved screenshot with base name `pic_name` in the `baselines` folder.
+pub fn get_baseline_path(baselines: &str, pic_name: &str) -> String {
+    format!("{}/{}.png", baselines, pic_name)
+}
+
 pub fn get_urls(the_config: &crate::config::Config) -> Result<Vec<String>, Box<dyn Error>> {
     if the_config.routes == String::from("sitemap") {
         println!("Getting urls to test from sitemap...");
@@ -61,3 +110,42 @@ pub fn get_urls(the_config: &crate::config::Config) -> Result<Vec<String>, Box<d
         Ok(the_config.routes.split(',').map(|x| x.to_string()).collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_pic_names_only_keep_file_name_characters() {
+        let slug = String::from("shop/checkout");
+        assert_eq!(
+            get_element_pic_name(&slug, "desktop", "form.checkout > button", None),
+            "shop_checkout_desktop_form_checkout___button_7801d3",
+        );
+        assert_eq!(get_element_pic_name(&slug, "mobile", "#site-header", None), "shop_checkout_mobile_site-header_30c55f");
+    }
+
+    #[test]
+    fn element_pic_names_differ_for_selectors_that_only_differ_in_punctuation() {
+        let slug = String::from("about");
+        assert_eq!(get_element_pic_name(&slug, "desktop", "#header", None), "about_desktop_header_6d78d1");
+        assert_eq!(get_element_pic_name(&slug, "desktop", ".header", None), "about_desktop_header_396d19");
+    }
+
+    #[test]
+    fn element_pic_names_number_each_of_several_matches() {
+        assert_eq!(get_element_pic_name("about", "desktop", "li", Some(2)), "about_desktop_li_d70c1e_2");
+
+        let dir = std::env::temp_dir().join(format!("nitpx-indexed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in &["about_desktop_li_d70c1e_testing.png", "about_desktop_li_d70c1e_0_testing.png",
+            "about_desktop_li_d70c1e_1_testing.png", "about_desktop_li_d70c1e_1_diff.png",
+            "about_desktop_li_d70c1e_x_testing.png"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        let pic_names = find_indexed_pic_names(&dir.to_string_lossy(), "about_desktop_li_d70c1e", "_testing.png");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(pic_names, vec!["about_desktop_li_d70c1e_0", "about_desktop_li_d70c1e_1"]);
+    }
+}